@@ -0,0 +1,116 @@
+use hmac::Hmac;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::model::User;
+
+#[cfg(not(test))]
+const PBKDF2_ROUNDS: u32 = 100_000;
+// Unoptimized test builds would spend seconds on every hash.
+#[cfg(test)]
+const PBKDF2_ROUNDS: u32 = 1_000;
+const SALT_LEN: usize = 16;
+const HASH_LEN: usize = 32;
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+/// A registered user. Guests from `/add` have no account, only a token.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Account {
+    pub user_id: usize,
+    pub login: String,
+    pub display_name: String,
+    pub preferred_color: Option<usize>,
+    salt: String,
+    password_hash: String,
+    rounds: u32,
+}
+
+impl Account {
+    pub fn new(user_id: usize, login: String, password: &str, display_name: String) -> Account {
+        let mut salt = [0u8; SALT_LEN];
+        rand::thread_rng().fill_bytes(&mut salt);
+        Account {
+            user_id,
+            login,
+            display_name,
+            preferred_color: None,
+            salt: hex::encode(salt),
+            password_hash: hex::encode(hash_password(password, &salt, PBKDF2_ROUNDS)),
+            rounds: PBKDF2_ROUNDS,
+        }
+    }
+
+    pub fn verify(&self, password: &str) -> bool {
+        match (hex::decode(&self.salt), hex::decode(&self.password_hash)) {
+            (Ok(salt), Ok(expected)) => constant_time_eq(&hash_password(password, &salt, self.rounds), &expected),
+            _ => false
+        }
+    }
+
+    pub fn user(&self) -> User {
+        User {
+            user_id: self.user_id,
+            user_name: self.display_name.clone(),
+            preferred_color: self.preferred_color,
+        }
+    }
+}
+
+/// Checks the password of the account. Without an account a made up hash is checked all the same,
+/// so the time of a failed login does not tell whether the login exists.
+pub fn verify_login(account: Option<&Account>, password: &str) -> bool {
+    match account {
+        Some(account) => account.verify(password),
+        None => {
+            std::hint::black_box(hash_password(password, &[0u8; SALT_LEN], PBKDF2_ROUNDS));
+            false
+        }
+    }
+}
+
+/// Logins are case insensitive, 3 to 32 letters, digits, '-', '_' or '.'.
+pub fn normalize_login(login: &str) -> Option<String> {
+    let login = login.trim().to_lowercase();
+    let valid_chars = login.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if valid_chars && (3..=32).contains(&login.chars().count()) {
+        Some(login)
+    } else {
+        None
+    }
+}
+
+fn hash_password(password: &str, salt: &[u8], rounds: u32) -> [u8; HASH_LEN] {
+    let mut hash = [0u8; HASH_LEN];
+    pbkdf2::pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, rounds, &mut hash);
+    hash
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_password_verification() {
+        let account = Account::new(1, "alice".to_string(), "correct horse", "Alice".to_string());
+        assert!(account.verify("correct horse"));
+        assert!(!account.verify("correct horse "));
+        assert_ne!(account.salt, Account::new(1, "alice".to_string(), "correct horse", "Alice".to_string()).salt);
+
+        let restored: Account = serde_json::from_str(&serde_json::to_string(&account).unwrap()).unwrap();
+        assert!(restored.verify("correct horse"));
+        assert!(verify_login(Some(&restored), "correct horse"));
+        assert!(!verify_login(None, "correct horse"));
+    }
+
+    #[test]
+    fn test_normalize_login() {
+        assert_eq!(Some("alice.b".to_string()), normalize_login(" Alice.B "));
+        assert_eq!(None, normalize_login("al"));
+        assert_eq!(None, normalize_login("alice smith"));
+    }
+}
@@ -0,0 +1,250 @@
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{BLUE, GREEN, NEUTRAL, ORANGE, PURPLE, RED, YELLOW};
+use crate::hex::{DIRECTIONS, Hex};
+
+pub const DEFAULT_BOARD_SIZE: usize = 5;
+pub const BOARD_SIZES: RangeInclusive<usize> = 3..=6;
+/// Colors of the triangles clockwise from the top.
+const TRIANGLES: [usize; 6] = [PURPLE, GREEN, ORANGE, YELLOW, RED, BLUE];
+
+/// Geometry of the star shaped board. `size` is the number of rows in each of the six triangles,
+/// a triangle holds the cones of one player: 6 for 3 rows, 10 for 4, 15 for 5 and 21 for 6.
+///
+/// Rows are numbered from the purple apex down to the yellow one, `4 * size + 1` rows in total,
+/// points in a row are numbered from the left starting at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Board {
+    pub size: usize,
+}
+
+impl Default for Board {
+    fn default() -> Board {
+        Board { size: DEFAULT_BOARD_SIZE }
+    }
+}
+
+impl Board {
+    pub fn new(size: usize) -> Option<Board> {
+        if BOARD_SIZES.contains(&size) {
+            Some(Board { size })
+        } else {
+            None
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        4 * self.size + 1
+    }
+
+    pub fn row_len(&self, row: usize) -> usize {
+        let n = self.size;
+        if row < n {
+            row + 1
+        } else if row <= 2 * n {
+            4 * n + 1 - row
+        } else if row <= 3 * n {
+            row + 1
+        } else {
+            self.rows() - row
+        }
+    }
+
+    // Counting the points of a row 2 apart, a point sits between two points of the rows above and below.
+    // This is where the first point of the row is, the leftmost point of the board being at 0.
+    fn row_offset(&self, row: usize) -> usize {
+        let n = self.size;
+        if row < n {
+            3 * n - row
+        } else if row <= 2 * n {
+            row - n
+        } else if row <= 3 * n {
+            3 * n - row
+        } else {
+            row - n
+        }
+    }
+
+    pub fn contains(&self, row: i32, col: i32) -> bool {
+        row >= 0 && (row as usize) < self.rows() && col >= 0 && (col as usize) < self.row_len(row as usize)
+    }
+
+    /// Axial coordinates of the point, with the center of the board at `(0, 0)`.
+    pub fn hex(&self, row: usize, col: usize) -> Hex {
+        let n = self.size as i32;
+        let r = row as i32 - 2 * n;
+        let x = (self.row_offset(row) + 2 * col) as i32;
+        Hex::new((x - 3 * n - r) / 2, r)
+    }
+
+    /// The `(row, col)` point at the axial coordinates, if it is on the board.
+    pub fn point(&self, hex: Hex) -> Option<(usize, usize)> {
+        let n = self.size as i32;
+        let row = hex.r + 2 * n;
+        if row < 0 || row as usize >= self.rows() {
+            return None;
+        }
+        let col = (2 * hex.q + hex.r + 3 * n - self.row_offset(row as usize) as i32) / 2;
+        if self.contains(row, col) {
+            Some((row as usize, col as usize))
+        } else {
+            None
+        }
+    }
+
+    /// Color of the triangle the point belongs to, `NEUTRAL` for the hexagon in the middle.
+    pub fn color(&self, row: usize, col: usize) -> usize {
+        let n = self.size as i32;
+        let hex = self.hex(row, col);
+        if hex.radius() <= n {
+            return NEUTRAL;
+        }
+        // Turning the board back by k sixths brings the k-th triangle from the top to the top.
+        (0..6).find(|k| hex.rotate(-k).r < -n).map_or(NEUTRAL, |k| TRIANGLES[k as usize])
+    }
+
+    pub fn points(&self) -> impl Iterator<Item=(usize, usize)> + '_ {
+        (0..self.rows()).flat_map(move |row| (0..self.row_len(row)).map(move |col| (row, col)))
+    }
+
+    /// The point in the middle of the board.
+    pub fn center(&self) -> (usize, usize) {
+        (2 * self.size, self.size)
+    }
+
+    pub fn neighbors(&self, row: usize, col: usize) -> Vec<(usize, usize)> {
+        self.hex(row, col).neighbors().filter_map(|h| self.point(h)).collect()
+    }
+
+    /// Lines of points going from `(row, col)` in each of the six `hex::DIRECTIONS`, up to the edge of the board.
+    /// The starting point is not included.
+    pub fn lines(&self, row: usize, col: usize) -> Vec<Vec<(usize, usize)>> {
+        let hex = self.hex(row, col);
+        DIRECTIONS.iter()
+            .map(|d| (1..)
+                .map(|step| self.point(hex + *d * step))
+                .take_while(|p| p.is_some())
+                .flatten()
+                .collect())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::get_complementary;
+
+    use super::*;
+
+    impl Board {
+        fn cones_per_player(&self) -> usize {
+            self.size * (self.size + 1) / 2
+        }
+    }
+
+    #[test]
+    fn test_point_counts() {
+        let board = Board::default();
+        let counts: Vec<usize> = (0..board.rows()).map(|r| board.row_len(r)).collect();
+        assert_eq!(vec![1, 2, 3, 4, 5, 16, 15, 14, 13, 12, 11, 12, 13, 14, 15, 16, 5, 4, 3, 2, 1], counts);
+        assert_eq!(181, board.points().count());
+        assert_eq!(121, Board::new(4).unwrap().points().count());
+        assert!(Board::new(2).is_none());
+    }
+
+    #[test]
+    fn test_triangles() {
+        for size in BOARD_SIZES {
+            let board = Board::new(size).unwrap();
+            for color in [PURPLE, GREEN, ORANGE, YELLOW, RED, BLUE].iter() {
+                assert_eq!(board.cones_per_player(), board.points().filter(|(r, c)| board.color(*r, *c) == *color).count());
+            }
+            let (row, col) = board.center();
+            assert_eq!(6, board.neighbors(row, col).len());
+            assert_eq!(NEUTRAL, board.color(row, col));
+        }
+        let board = Board::new(3).unwrap();
+        assert_eq!(vec![BLUE, BLUE, BLUE, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, GREEN, GREEN, GREEN],
+                   (0..10).map(|c| board.color(3, c)).collect::<Vec<usize>>());
+        assert_eq!(YELLOW, board.color(12, 0));
+    }
+
+    #[test]
+    fn test_lines() {
+        let board = Board::default();
+        let lines = board.lines(4, 0);
+        assert_eq!(vec![(3, 0), (2, 0), (1, 0), (0, 0)], lines[3]);
+        assert_eq!(vec![(5, 6), (6, 6), (7, 6), (8, 6), (9, 6), (10, 6), (11, 7), (12, 8), (13, 9), (14, 10), (15, 11)], lines[5]);
+        assert_eq!(vec![(5, 5), (6, 4), (7, 3), (8, 2), (9, 1), (10, 0), (11, 0), (12, 0), (13, 0), (14, 0), (15, 0)], lines[4]);
+        assert!(lines[0].is_empty());
+        // The corner of the blue triangle has nothing above it.
+        assert!(board.lines(5, 0)[3].is_empty());
+    }
+
+    #[test]
+    fn test_hex_conversion() {
+        for size in BOARD_SIZES {
+            let board = Board::new(size).unwrap();
+            let n = size as i32;
+            assert_eq!(Hex::new(0, 0), board.hex(board.center().0, board.center().1));
+            for (row, col) in board.points() {
+                assert_eq!(Some((row, col)), board.point(board.hex(row, col)));
+            }
+            // The star is made of two big triangles, one pointing up and one pointing down.
+            let mut on_board = 0;
+            for q in -3 * n..=3 * n {
+                for r in -3 * n..=3 * n {
+                    let h = Hex::new(q, r);
+                    let star = q.max(r).max(h.s()) <= n || q.min(r).min(h.s()) >= -n;
+                    assert_eq!(star, board.point(h).is_some(), "{:?}", h);
+                    on_board += star as usize;
+                }
+            }
+            assert_eq!(board.points().count(), on_board);
+        }
+    }
+
+    #[test]
+    fn test_neighbors_are_one_step_away() {
+        for size in BOARD_SIZES {
+            let board = Board::new(size).unwrap();
+            for (row, col) in board.points() {
+                let hex = board.hex(row, col);
+                let mut expected: Vec<(usize, usize)> = board.points().filter(|(r, c)| hex.distance(board.hex(*r, *c)) == 1).collect();
+                let mut neighbors = board.neighbors(row, col);
+                expected.sort_unstable();
+                neighbors.sort_unstable();
+                assert_eq!(expected, neighbors, "{:?}", (row, col));
+            }
+        }
+    }
+
+    #[test]
+    fn test_rotation_symmetry() {
+        for size in BOARD_SIZES {
+            let board = Board::new(size).unwrap();
+            for (row, col) in board.points() {
+                let color = board.color(row, col);
+                let (r, c) = board.point(board.hex(row, col).rotate(1)).unwrap();
+                let next = TRIANGLES.iter().position(|t| *t == color).map_or(NEUTRAL, |i| TRIANGLES[(i + 1) % 6]);
+                assert_eq!(next, board.color(r, c));
+                let (r, c) = board.point(board.hex(row, col).rotate(3)).unwrap();
+                assert_eq!(*get_complementary(&color), board.color(r, c));
+            }
+        }
+    }
+
+    #[test]
+    fn test_neighbors_are_symmetric() {
+        for size in BOARD_SIZES {
+            let board = Board::new(size).unwrap();
+            for (row, col) in board.points() {
+                for (r, c) in board.neighbors(row, col) {
+                    assert!(board.neighbors(r, c).contains(&(row, col)), "{:?} -> {:?}", (row, col), (r, c));
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
+
+use log::{error, info};
+use rand::{RngCore, SeedableRng};
+use rand::rngs::StdRng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio::time::Duration;
+
+use crate::{RoomHandle, RoomList, RoomTimersList};
+use crate::game::{GameState, get_complementary, LegalMove};
+use crate::handler;
+use crate::model::{Player, PublishToARoomRequest};
+
+const BOT_MOVE_DELAY_MS: u64 = 1000;
+// Weight of the cone that is farthest behind, so that bots do not leave stragglers in their home triangle.
+const STRAGGLER_WEIGHT: i64 = 2;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    Easy,
+    Medium,
+    Hard,
+}
+
+#[derive(Debug)]
+pub struct Bot {
+    pub difficulty: Difficulty,
+    pub seed: u64,
+    /// Numbers taken from the generator so far, a restored bot skips them to go on with the same stream.
+    pub draws: u64,
+    rng: StdRng,
+}
+
+impl Bot {
+    pub fn new(difficulty: Difficulty, seed: u64) -> Bot {
+        Bot::resume(difficulty, seed, 0)
+    }
+
+    pub fn resume(difficulty: Difficulty, seed: u64, draws: u64) -> Bot {
+        let mut rng = StdRng::seed_from_u64(seed);
+        for _ in 0..draws {
+            rng.next_u64();
+        }
+        Bot {
+            difficulty,
+            seed,
+            draws,
+            rng,
+        }
+    }
+
+    pub fn player(user_id: usize, name: String, bot: Bot) -> Player {
+        // Nobody listens to a bot's updates, the receiver is dropped right away.
+        let (sender, _) = mpsc::unbounded_channel();
+        Player {
+            user_id,
+            name: Some(name),
+            sender,
+            ready: true,
+            last_active: Instant::now(),
+            bot: Some(bot),
+        }
+    }
+
+    pub fn choose_move(&mut self, gs: &GameState, user_id: usize) -> Option<Vec<(i32, i32)>> {
+        let target = *get_complementary(gs.players_colors.get(&user_id)?);
+        let distances = distances_to_apex(gs, target);
+        let moves = gs.all_legal_moves(&user_id).ok()?;
+        if moves.is_empty() {
+            return None;
+        }
+        let current = evaluate(gs, user_id, &distances);
+        let scored: Vec<(i64, &LegalMove)> = moves.iter()
+            .map(|m| {
+                let next = apply(gs, m);
+                let mut score = current - evaluate(&next, user_id, &distances);
+                if self.difficulty == Difficulty::Hard {
+                    score = score * 2 + best_progress(&next, user_id, &distances);
+                }
+                (score, m)
+            })
+            .collect();
+        let candidates: Vec<&LegalMove> = match self.difficulty {
+            Difficulty::Easy => {
+                let forward: Vec<&LegalMove> = scored.iter().filter(|(s, _)| *s > 0).map(|(_, m)| *m).collect();
+                if forward.is_empty() { scored.iter().map(|(_, m)| *m).collect() } else { forward }
+            }
+            Difficulty::Medium | Difficulty::Hard => {
+                let best = scored.iter().map(|(s, _)| *s).max()?;
+                scored.iter().filter(|(s, _)| *s == best).map(|(_, m)| *m).collect()
+            }
+        };
+        // One number per move whatever the choice, so that the draws can be counted and skipped.
+        self.draws += 1;
+        let chosen = candidates[(self.rng.next_u64() % candidates.len() as u64) as usize];
+        Some(chosen.path.iter().map(|(r, c)| (*r as i32, *c as i32)).collect())
+    }
+}
+
+fn apply(gs: &GameState, m: &LegalMove) -> GameState {
+    let mut next = gs.clone();
+    if let Some(id) = next.cones.remove(&m.from) {
+        next.cones.insert(m.to, id);
+    }
+    next
+}
+
+fn evaluate(gs: &GameState, user_id: usize, distances: &HashMap<(usize, usize), i64>) -> i64 {
+    let cones: Vec<i64> = gs.get_cones(&user_id).iter()
+        .map(|p| distances.get(p).cloned().unwrap_or(0))
+        .collect();
+    cones.iter().sum::<i64>() + STRAGGLER_WEIGHT * cones.iter().max().cloned().unwrap_or(0)
+}
+
+fn best_progress(gs: &GameState, user_id: usize, distances: &HashMap<(usize, usize), i64>) -> i64 {
+    let current = evaluate(gs, user_id, distances);
+    gs.all_legal_moves(&user_id).unwrap_or_default().iter()
+        .map(|m| current - evaluate(&apply(gs, m), user_id, distances))
+        .max()
+        .unwrap_or(0)
+}
+
+// Step distance on the empty board from every point to the far corner of the target triangle.
+fn distances_to_apex(gs: &GameState, target: usize) -> HashMap<(usize, usize), i64> {
+    let apex = gs.board.points()
+        .filter(|(row, col)| gs.board.color(*row, *col) == target)
+        .max_by_key(|(row, col)| gs.board.hex(*row, *col).radius())
+        .unwrap_or_else(|| gs.board.center());
+    bfs(gs, apex)
+}
+
+fn bfs(gs: &GameState, start: (usize, usize)) -> HashMap<(usize, usize), i64> {
+    let mut result = HashMap::new();
+    let mut queue = VecDeque::new();
+    result.insert(start, 0);
+    queue.push_back(start);
+    while let Some(p) = queue.pop_front() {
+        let d = result[&p];
+        for n in gs.get_neighbors(p.0 as i32, p.1 as i32).unwrap_or_default() {
+            result.entry(n).or_insert_with(|| {
+                queue.push_back(n);
+                d + 1
+            });
+        }
+    }
+    result
+}
+
+// Callers hold the rooms lock already, so the room is passed in and the move is made from a separate task.
+pub fn schedule_bot_move(rooms: RoomList, rooms_timers: RoomTimersList, room: &RoomHandle) {
+    if !room.game_started || room.game_finished {
+        return;
+    }
+    let bot_id = match room.players.get(room.active_player) {
+        Some(p) if p.bot.is_some() => p.user_id,
+        _ => return
+    };
+    let room_id = room.room_id.clone();
+    tokio::spawn(async move {
+        tokio::time::delay_for(Duration::from_millis(BOT_MOVE_DELAY_MS)).await;
+        let path = {
+            let mut lock = rooms.write().unwrap();
+            let r = match lock.get_mut(&room_id) {
+                Some(r) => r,
+                None => return
+            };
+            let gs = match r.game_state.as_ref() {
+                Some(gs) => gs,
+                None => return
+            };
+            match r.players.get_mut(r.active_player) {
+                Some(Player { user_id, bot: Some(bot), .. }) if *user_id == bot_id => bot.choose_move(gs, bot_id),
+                _ => return
+            }
+        };
+        match path {
+            Some(p) => {
+                info!("Bot {} moves along {:?} in room {}", bot_id, p, room_id);
+                let request = PublishToARoomRequest {
+                    path: p.iter().map(|(r, c)| vec![*r, *c]).collect(),
+                    calculate_path: false,
+                };
+                if handler::make_a_move(room_id, bot_id, rooms_timers, rooms, request).is_err() {
+                    error!("Bot {} failed to make a move.", bot_id);
+                }
+            }
+            None => info!("Bot {} has no moves, waiting for the timer.", bot_id)
+        }
+    });
+}
+
+/// Lets the bots of restored games move again, a bot on turn would otherwise wait for the move timer.
+pub fn resume_bots(rooms: RoomList, rooms_timers: RoomTimersList, room_ids: &[String]) {
+    let lock = rooms.read().unwrap();
+    for room in room_ids.iter().filter_map(|id| lock.get(id)) {
+        schedule_bot_move(rooms.clone(), rooms_timers.clone(), room);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::PURPLE;
+
+    use super::*;
+
+    fn play(bot: &mut Bot, gs: &mut GameState, user_id: usize, max_moves: usize) -> Vec<Vec<(i32, i32)>> {
+        let mut paths = Vec::new();
+        for _ in 0..max_moves {
+            if gs.is_all_cones_in_place(&user_id).unwrap() {
+                break;
+            }
+            let path = bot.choose_move(gs, user_id).unwrap();
+            gs.update_cones(&path, &user_id).unwrap();
+            paths.push(path);
+        }
+        paths
+    }
+
+    #[test]
+    fn test_same_seed_same_game() {
+        let mut first = GameState::new();
+        let mut second = GameState::new();
+        first.add_cones(0, PURPLE).unwrap();
+        second.add_cones(0, PURPLE).unwrap();
+        let a = play(&mut Bot::new(Difficulty::Easy, 42), &mut first, 0, 20);
+        let b = play(&mut Bot::new(Difficulty::Easy, 42), &mut second, 0, 20);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_resumed_bot_goes_on_with_the_same_stream() {
+        let mut gs = GameState::new();
+        gs.add_cones(0, PURPLE).unwrap();
+        let mut bot = Bot::new(Difficulty::Easy, 42);
+        play(&mut bot, &mut gs, 0, 5);
+        let mut resumed = Bot::resume(Difficulty::Easy, 42, bot.draws);
+        let mut copy = gs.clone();
+        assert_eq!(play(&mut bot, &mut gs, 0, 10), play(&mut resumed, &mut copy, 0, 10));
+    }
+
+    #[test]
+    fn test_bot_reaches_target_triangle() {
+        let mut gs = GameState::new();
+        gs.add_cones(0, PURPLE).unwrap();
+        play(&mut Bot::new(Difficulty::Medium, 7), &mut gs, 0, 200);
+        assert!(gs.is_all_cones_in_place(&0).unwrap());
+    }
+
+    #[test]
+    fn test_hard_bot_prefers_long_jumps() {
+        let mut gs = GameState::new();
+        gs.add_cones(0, PURPLE).unwrap();
+        gs.add_cone(5, 7, 1).unwrap();
+        gs.add_cone(7, 7, 1).unwrap();
+        let path = Bot::new(Difficulty::Hard, 7).choose_move(&gs, 0).unwrap();
+        assert!(path.len() > 2, "expected a jump, got {:?}", path);
+    }
+}
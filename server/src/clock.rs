@@ -0,0 +1,222 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_MOVE_TIME_SEC: usize = 30;
+
+/// Time limits of a room, all values are in seconds.
+///
+/// `per_move_sec` limits a single turn, `total_sec` is the chess clock of every player.
+/// `increment_sec` is added to the player's clock after each move (Fischer),
+/// after each move the time used on it is given back up to `delay_sec` (Bronstein).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TimeControl {
+    pub per_move_sec: Option<usize>,
+    pub total_sec: Option<usize>,
+    #[serde(default)]
+    pub increment_sec: usize,
+    #[serde(default)]
+    pub delay_sec: usize,
+}
+
+impl Default for TimeControl {
+    fn default() -> Self {
+        TimeControl {
+            per_move_sec: Some(DEFAULT_MOVE_TIME_SEC),
+            total_sec: None,
+            increment_sec: 0,
+            delay_sec: 0,
+        }
+    }
+}
+
+impl TimeControl {
+    pub fn is_valid(&self) -> bool {
+        match (self.per_move_sec, self.total_sec) {
+            (None, None) => false,
+            (per_move, total) => per_move != Some(0) && total != Some(0)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum TimeoutAction {
+    #[default]
+    Skip,
+    AutoMove,
+}
+
+/// What happens when a player runs out of time: the turn is skipped or played by the server,
+/// and after `forfeit_after` consecutive timeouts the player is out of the game.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+pub struct TimeoutPolicy {
+    #[serde(default)]
+    pub action: TimeoutAction,
+    pub forfeit_after: Option<usize>,
+}
+
+impl TimeoutPolicy {
+    pub fn is_valid(&self) -> bool {
+        self.forfeit_after != Some(0)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GameClock {
+    pub control: TimeControl,
+    pub remaining: HashMap<usize, usize>,
+    pub turn_elapsed: usize,
+    #[serde(default)]
+    pub policy: TimeoutPolicy,
+    #[serde(default)]
+    pub timeouts: HashMap<usize, usize>,
+}
+
+impl GameClock {
+    pub fn new(control: TimeControl, policy: TimeoutPolicy) -> GameClock {
+        GameClock {
+            control,
+            remaining: HashMap::new(),
+            turn_elapsed: 0,
+            policy,
+            timeouts: HashMap::new(),
+        }
+    }
+
+    pub fn start(&mut self, user_ids: impl Iterator<Item=usize>) {
+        self.remaining.clear();
+        if let Some(total) = self.control.total_sec {
+            self.remaining.extend(user_ids.map(|id| (id, total)));
+        }
+        self.timeouts.clear();
+        self.turn_elapsed = 0;
+    }
+
+    /// Counts one second of the user's turn, returns true when the turn is out of time.
+    pub fn tick(&mut self, user_id: usize) -> bool {
+        self.turn_elapsed += 1;
+        if let Some(r) = self.remaining.get_mut(&user_id) {
+            *r = r.saturating_sub(1);
+        }
+        self.time_left(user_id) == 0
+    }
+
+    pub fn move_made(&mut self, user_id: usize) {
+        let delay = self.control.delay_sec.min(self.turn_elapsed);
+        if let Some(r) = self.remaining.get_mut(&user_id) {
+            *r += self.control.increment_sec + delay;
+        }
+        self.timeouts.remove(&user_id);
+        self.turn_elapsed = 0;
+    }
+
+    /// Returns the number of consecutive timeouts of the user, including this one.
+    pub fn timed_out(&mut self, user_id: usize) -> usize {
+        let count = self.timeouts.entry(user_id).or_insert(0);
+        *count += 1;
+        *count
+    }
+
+    pub fn should_forfeit(&self, user_id: usize) -> bool {
+        match (self.policy.forfeit_after, self.timeouts.get(&user_id)) {
+            (Some(limit), Some(count)) => *count >= limit,
+            _ => false
+        }
+    }
+
+    pub fn turn_passed(&mut self) {
+        self.turn_elapsed = 0;
+    }
+
+    /// Seconds the user has left for the current turn.
+    pub fn time_left(&self, user_id: usize) -> usize {
+        let per_move = self.control.per_move_sec.map(|limit| limit.saturating_sub(self.turn_elapsed));
+        let total = self.remaining.get(&user_id).cloned();
+        match (per_move, total) {
+            (Some(m), Some(t)) => m.min(t),
+            (Some(m), None) => m,
+            (None, Some(t)) => t,
+            (None, None) => usize::MAX
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clock(per_move_sec: Option<usize>, total_sec: Option<usize>, increment_sec: usize, delay_sec: usize) -> GameClock {
+        let mut clock = GameClock::new(TimeControl { per_move_sec, total_sec, increment_sec, delay_sec }, TimeoutPolicy::default());
+        clock.start(vec![1, 2].into_iter());
+        clock
+    }
+
+    #[test]
+    fn test_per_move_limit() {
+        let mut c = clock(Some(3), None, 0, 0);
+        assert!(!c.tick(1));
+        assert!(!c.tick(1));
+        assert!(c.tick(1));
+        c.turn_passed();
+        assert_eq!(3, c.time_left(2));
+        assert!(c.remaining.is_empty());
+    }
+
+    #[test]
+    fn test_total_clock_with_increment() {
+        let mut c = clock(Some(10), Some(5), 2, 0);
+        for _ in 0..3 {
+            c.tick(1);
+        }
+        c.move_made(1);
+        assert_eq!(4, c.remaining[&1]);
+        assert_eq!(5, c.remaining[&2]);
+        for _ in 0..3 {
+            assert!(!c.tick(1));
+        }
+        assert!(c.tick(1));
+    }
+
+    #[test]
+    fn test_bronstein_delay() {
+        let mut c = clock(None, Some(5), 0, 3);
+        c.tick(1);
+        c.tick(1);
+        assert_eq!(3, c.time_left(1));
+        // A quick move gets all of its time back.
+        c.move_made(1);
+        assert_eq!(5, c.remaining[&1]);
+        for _ in 0..4 {
+            c.tick(1);
+        }
+        // A slow one at most the delay.
+        c.move_made(1);
+        assert_eq!(4, c.remaining[&1]);
+        // The delay does not stretch the turn, the clock runs out as usual.
+        for _ in 0..3 {
+            assert!(!c.tick(1));
+        }
+        assert!(c.tick(1));
+    }
+
+    #[test]
+    fn test_consecutive_timeouts() {
+        let mut c = clock(Some(3), None, 0, 0);
+        c.policy.forfeit_after = Some(2);
+        assert_eq!(1, c.timed_out(1));
+        assert!(!c.should_forfeit(1));
+        c.move_made(1);
+        assert_eq!(1, c.timed_out(1));
+        assert_eq!(2, c.timed_out(1));
+        assert!(c.should_forfeit(1));
+        assert!(!c.should_forfeit(2));
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(TimeControl::default().is_valid());
+        assert!(!TimeControl { per_move_sec: None, total_sec: None, increment_sec: 1, delay_sec: 0 }.is_valid());
+        assert!(!TimeControl { per_move_sec: Some(0), total_sec: Some(60), increment_sec: 0, delay_sec: 0 }.is_valid());
+    }
+}
@@ -0,0 +1,242 @@
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+
+const MIN_SECRET_LEN: usize = 32;
+
+/// Server settings. Values come from the defaults, then the TOML file given with `--config`,
+/// then environment variables and command line flags, the latter taking precedence.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    pub room_ttl_sec: u64,
+    pub player_ttl_sec: u64,
+    pub token_ttl_sec: u64,
+    pub move_time_sec: usize,
+    /// Allowed CORS origins, any origin is allowed when empty.
+    pub cors_origins: Vec<String>,
+    /// Same syntax as RUST_LOG, e.g. "info" or "info,warp=debug".
+    pub log_level: String,
+    pub storage_file: PathBuf,
+    pub snapshot_interval_sec: u64,
+    /// Base URL clients reach the server at, with scheme and path prefix, e.g. "https://example.org/api".
+    /// Defaults to the bind address.
+    pub public_url: Option<String>,
+    /// Base URL of the web client, used for invitation links.
+    pub client_url: Option<String>,
+    /// Key for signing session tokens. A random one is used when missing,
+    /// then tokens are only valid until the server restarts.
+    pub token_secret: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: "127.0.0.1".to_string(),
+            port: 8000,
+            room_ttl_sec: 600,
+            player_ttl_sec: 40,
+            token_ttl_sec: 3600 * 24,
+            move_time_sec: 30,
+            cors_origins: Vec::new(),
+            log_level: "info".to_string(),
+            storage_file: PathBuf::from("sterligov-game-state.json"),
+            snapshot_interval_sec: 10,
+            public_url: None,
+            client_url: None,
+            token_secret: None,
+        }
+    }
+}
+
+#[derive(StructOpt, Debug, Default)]
+#[structopt(name = "chess-game-server", about = "Server for the Sterligov Chinese checkers game.")]
+pub struct Opts {
+    /// TOML configuration file
+    #[structopt(short, long, env = "STERLIGOV_CONFIG", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+    /// Print the effective configuration and exit
+    #[structopt(long)]
+    pub print_config: bool,
+    #[structopt(long, env = "STERLIGOV_HOST")]
+    pub host: Option<String>,
+    #[structopt(short, long, env = "STERLIGOV_PORT")]
+    pub port: Option<u16>,
+    #[structopt(long, env = "STERLIGOV_ROOM_TTL_SEC")]
+    pub room_ttl_sec: Option<u64>,
+    #[structopt(long, env = "STERLIGOV_PLAYER_TTL_SEC")]
+    pub player_ttl_sec: Option<u64>,
+    #[structopt(long, env = "STERLIGOV_TOKEN_TTL_SEC")]
+    pub token_ttl_sec: Option<u64>,
+    /// Default per-move time limit for rooms created without time controls
+    #[structopt(long, env = "STERLIGOV_MOVE_TIME_SEC")]
+    pub move_time_sec: Option<usize>,
+    /// Comma separated list of allowed origins
+    #[structopt(long, env = "STERLIGOV_CORS_ORIGINS", use_delimiter = true)]
+    pub cors_origins: Option<Vec<String>>,
+    #[structopt(long, env = "STERLIGOV_LOG_LEVEL")]
+    pub log_level: Option<String>,
+    #[structopt(long, env = "STERLIGOV_STORAGE_FILE", parse(from_os_str))]
+    pub storage_file: Option<PathBuf>,
+    #[structopt(long, env = "STERLIGOV_SNAPSHOT_INTERVAL_SEC")]
+    pub snapshot_interval_sec: Option<u64>,
+    /// External base URL of the server, e.g. https://example.org/api
+    #[structopt(long, env = "STERLIGOV_PUBLIC_URL")]
+    pub public_url: Option<String>,
+    /// Base URL of the web client for invitation links
+    #[structopt(long, env = "STERLIGOV_CLIENT_URL")]
+    pub client_url: Option<String>,
+    /// Key for signing session tokens, at least 32 characters
+    #[structopt(long, env = "STERLIGOV_TOKEN_SECRET", hide_env_values = true)]
+    pub token_secret: Option<String>,
+}
+
+impl Opts {
+    pub fn into_config(self) -> Result<Config, String> {
+        let mut config = match &self.config {
+            Some(path) => {
+                let text = fs::read_to_string(path)
+                    .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+                toml::from_str(&text).map_err(|e| format!("Could not parse {}: {}", path.display(), e))?
+            }
+            None => Config::default()
+        };
+        self.apply(&mut config);
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn apply(self, config: &mut Config) {
+        if let Some(v) = self.host { config.host = v; }
+        if let Some(v) = self.port { config.port = v; }
+        if let Some(v) = self.room_ttl_sec { config.room_ttl_sec = v; }
+        if let Some(v) = self.player_ttl_sec { config.player_ttl_sec = v; }
+        if let Some(v) = self.token_ttl_sec { config.token_ttl_sec = v; }
+        if let Some(v) = self.move_time_sec { config.move_time_sec = v; }
+        if let Some(v) = self.cors_origins { config.cors_origins = v; }
+        if let Some(v) = self.log_level { config.log_level = v; }
+        if let Some(v) = self.storage_file { config.storage_file = v; }
+        if let Some(v) = self.snapshot_interval_sec { config.snapshot_interval_sec = v; }
+        if let Some(v) = self.public_url { config.public_url = Some(v); }
+        if let Some(v) = self.client_url { config.client_url = Some(v); }
+        if let Some(v) = self.token_secret { config.token_secret = Some(v); }
+    }
+}
+
+impl Config {
+    pub fn validate(&self) -> Result<(), String> {
+        self.host.parse::<IpAddr>().map_err(|e| format!("Invalid host {}: {}", self.host, e))?;
+        if self.port == 0 {
+            return Err("Port must not be 0".to_string());
+        }
+        for (name, value) in [
+            ("room_ttl_sec", self.room_ttl_sec),
+            ("player_ttl_sec", self.player_ttl_sec),
+            ("token_ttl_sec", self.token_ttl_sec),
+            ("move_time_sec", self.move_time_sec as u64),
+            ("snapshot_interval_sec", self.snapshot_interval_sec),
+        ].iter() {
+            if *value == 0 {
+                return Err(format!("{} must be positive", name));
+            }
+        }
+        if let Some(origin) = self.cors_origins.iter().find(|o| !is_http_url(o)) {
+            return Err(format!("Invalid CORS origin {}, expected scheme://host[:port]", origin));
+        }
+        for directive in self.log_level.split(',').filter(|d| !d.is_empty()) {
+            let level = directive.rsplit('=').next().unwrap_or(directive);
+            level.parse::<LevelFilter>().map_err(|_| format!("Invalid log level {}", directive))?;
+        }
+        if let Some(url) = self.public_url.iter().chain(self.client_url.iter()).find(|u| !is_http_url(u)) {
+            return Err(format!("Invalid URL {}, expected http(s)://host[:port][/prefix]", url));
+        }
+        if self.token_secret.as_ref().is_some_and(|s| s.len() < MIN_SECRET_LEN) {
+            return Err(format!("token_secret must be at least {} characters long", MIN_SECRET_LEN));
+        }
+        if self.storage_file.as_os_str().is_empty() {
+            return Err("storage_file must not be empty".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn bind_address(&self) -> SocketAddr {
+        SocketAddr::new(self.host.parse().expect("host is validated"), self.port)
+    }
+
+    pub fn public_url(&self) -> String {
+        match &self.public_url {
+            Some(url) => url.trim_end_matches('/').to_string(),
+            None => format!("http://{}:{}", self.host, self.port)
+        }
+    }
+
+    /// Same as the public URL, with the WebSocket scheme.
+    pub fn socket_url(&self) -> String {
+        let url = self.public_url();
+        match url.strip_prefix("https://") {
+            Some(rest) => format!("wss://{}", rest),
+            None => format!("ws://{}", url.trim_start_matches("http://"))
+        }
+    }
+
+    pub fn invite_url(&self, room_id: &str) -> Option<String> {
+        self.client_url.as_ref().map(|url| format!("{}/#/room/{}", url.trim_end_matches('/'), room_id))
+    }
+}
+
+fn is_http_url(url: &str) -> bool {
+    url.starts_with("http://") || url.starts_with("https://")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_values_are_overridden_by_flags() {
+        let mut config: Config = toml::from_str("port = 9000\nlog_level = \"debug\"\ncors_origins = [\"https://example.org\"]").unwrap();
+        assert_eq!(600, config.room_ttl_sec);
+        Opts { port: Some(9100), ..Opts::default() }.apply(&mut config);
+        assert_eq!(9100, config.port);
+        assert_eq!("debug", config.log_level);
+        assert_eq!(vec!["https://example.org".to_string()], config.cors_origins);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_public_urls() {
+        let config = Config::default();
+        assert_eq!("http://127.0.0.1:8000", config.public_url());
+        assert_eq!("ws://127.0.0.1:8000", config.socket_url());
+        assert_eq!(None, config.invite_url("room"));
+
+        let config = Config {
+            public_url: Some("https://example.org/api/".to_string()),
+            client_url: Some("https://example.org".to_string()),
+            ..Config::default()
+        };
+        assert!(config.validate().is_ok());
+        assert_eq!("https://example.org/api", config.public_url());
+        assert_eq!("wss://example.org/api", config.socket_url());
+        assert_eq!(Some("https://example.org/#/room/room".to_string()), config.invite_url("room"));
+        assert!(Config { public_url: Some("example.org/api".to_string()), ..Config::default() }.validate().is_err());
+    }
+
+    #[test]
+    fn test_validation() {
+        assert!(Config::default().validate().is_ok());
+        assert!(Config { host: "localhost:80".to_string(), ..Config::default() }.validate().is_err());
+        assert!(Config { player_ttl_sec: 0, ..Config::default() }.validate().is_err());
+        assert!(Config { cors_origins: vec!["example.org".to_string()], ..Config::default() }.validate().is_err());
+        assert!(Config { log_level: "info,warp=loud".to_string(), ..Config::default() }.validate().is_err());
+        assert!(Config { log_level: "warn,warp=debug".to_string(), ..Config::default() }.validate().is_ok());
+        assert!(toml::from_str::<Config>("prot = 80").is_err());
+        assert!(Config { token_secret: Some("short".to_string()), ..Config::default() }.validate().is_err());
+    }
+}
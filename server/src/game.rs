@@ -1,9 +1,14 @@
 use std::collections::{HashMap, HashSet};
 use std::collections::vec_deque::VecDeque;
+use std::time::SystemTime;
 
-use serde::{Deserialize, Serialize, Serializer};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::de::Error;
 use serde::ser::SerializeMap;
 
+use crate::board::Board;
+use crate::hex::DIRECTIONS;
+
 pub const NEUTRAL: usize = 0;
 pub const PURPLE: usize = 1;
 pub const GREEN: usize = 2;
@@ -11,30 +16,6 @@ pub const ORANGE: usize = 3;
 pub const YELLOW: usize = 4;
 pub const RED: usize = 5;
 pub const BLUE: usize = 6;
-pub const POINT_COUNTS: [usize; 21] = [1, 2, 3, 4, 5, 16, 15, 14, 13, 12, 11, 12, 13, 14, 15, 16, 5, 4, 3, 2, 1];
-pub const POINTS: &'static [&'static [usize]] = &[
-    &[PURPLE],
-    &[PURPLE, PURPLE],
-    &[PURPLE, PURPLE, PURPLE],
-    &[PURPLE, PURPLE, PURPLE, PURPLE],
-    &[PURPLE, PURPLE, PURPLE, PURPLE, PURPLE],
-    &[BLUE, BLUE, BLUE, BLUE, BLUE, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, GREEN, GREEN, GREEN, GREEN, GREEN],
-    &[BLUE, BLUE, BLUE, BLUE, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, GREEN, GREEN, GREEN, GREEN],
-    &[BLUE, BLUE, BLUE, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, GREEN, GREEN, GREEN],
-    &[BLUE, BLUE, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, GREEN, GREEN],
-    &[BLUE, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, GREEN],
-    &[NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL],
-    &[RED, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, ORANGE],
-    &[RED, RED, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, ORANGE, ORANGE],
-    &[RED, RED, RED, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, ORANGE, ORANGE, ORANGE],
-    &[RED, RED, RED, RED, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, ORANGE, ORANGE, ORANGE, ORANGE],
-    &[RED, RED, RED, RED, RED, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, NEUTRAL, ORANGE, ORANGE, ORANGE, ORANGE, ORANGE],
-    &[YELLOW, YELLOW, YELLOW, YELLOW, YELLOW],
-    &[YELLOW, YELLOW, YELLOW, YELLOW],
-    &[YELLOW, YELLOW, YELLOW],
-    &[YELLOW, YELLOW],
-    &[YELLOW]
-];
 
 pub fn get_complementary(color: &usize) -> &'static usize {
     match *color {
@@ -48,10 +29,58 @@ pub fn get_complementary(color: &usize) -> &'static usize {
     }
 }
 
-//180 places in total.
+/// House rules a room is played with, all of them are off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Rules {
+    /// A cone may jump over another one any number of points away along a line,
+    /// landing as many points behind it, when all the points passed are empty.
+    pub super_jumps: bool,
+    /// Moves may not end in the triangles of other colors, jumping through them is allowed.
+    pub no_parking: bool,
+    /// A player whose destination triangle is full has finished, if at least one of the cones there is theirs.
+    pub spoiler: bool,
+    /// A cone that reached the destination triangle may not leave it again.
+    pub no_leaving_destination: bool,
+    /// Only own cones and the cones of partners can be jumped over.
+    pub partner_jumps_only: bool,
+}
+
+const RULE_NAMES: [&str; 5] = ["super-jumps", "no-parking", "spoiler", "no-leaving-destination", "partner-jumps-only"];
+
+impl Rules {
+    fn flags(&mut self) -> [&mut bool; 5] {
+        [&mut self.super_jumps, &mut self.no_parking, &mut self.spoiler, &mut self.no_leaving_destination, &mut self.partner_jumps_only]
+    }
+
+    /// Names of the rules that are on, as used in game records.
+    pub fn names(&self) -> Vec<&'static str> {
+        let on = [self.super_jumps, self.no_parking, self.spoiler, self.no_leaving_destination, self.partner_jumps_only];
+        RULE_NAMES.iter().zip(on.iter()).filter(|(_, on)| **on).map(|(name, _)| *name).collect()
+    }
+
+    /// Turns on the rule with the given name, returns false if there is no such rule.
+    pub fn enable(&mut self, name: &str) -> bool {
+        match RULE_NAMES.iter().position(|n| *n == name) {
+            Some(i) => {
+                *self.flags()[i] = true;
+                true
+            }
+            None => false
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct GameState {
-    #[serde(serialize_with = "serialize_cones")]
+    #[serde(default)]
+    pub board: Board,
+    #[serde(default)]
+    pub rules: Rules,
+    /// Colors of the partners in each team, empty when everybody plays alone.
+    #[serde(default)]
+    pub teams: Vec<Vec<usize>>,
+    #[serde(serialize_with = "serialize_cones", deserialize_with = "deserialize_cones")]
     pub cones: HashMap<(usize, usize), usize>,
     //(row, position, color)
     pub players_colors: HashMap<usize, usize>,
@@ -59,6 +88,83 @@ pub struct GameState {
     pub moves: VecDeque<(usize, Vec<(usize, usize)>)>, //(user_id, [path])
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MoveRecord {
+    pub user_id: usize,
+    pub color: usize,
+    pub path: Vec<(usize, usize)>,
+    #[serde(with = "serde_millis")]
+    pub timestamp: SystemTime,
+}
+
+// Complete list of moves of a room, unlike GameState.moves which keeps only the latest ones.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct GameHistory {
+    #[serde(default)]
+    pub board: Board,
+    #[serde(default)]
+    pub rules: Rules,
+    #[serde(default)]
+    pub teams: Vec<Vec<usize>>,
+    pub initial_colors: HashMap<usize, usize>,
+    //(user_id, color)
+    pub moves: Vec<MoveRecord>,
+    /// Players who forfeited and the number of moves made before, their cones leave the board then.
+    #[serde(default)]
+    pub forfeits: Vec<(usize, usize)>,
+}
+
+impl GameHistory {
+    /// History of a game on the board and with the rules of `gs`.
+    pub fn new(gs: &GameState) -> GameHistory {
+        GameHistory { board: gs.board, rules: gs.rules, teams: gs.teams.clone(), ..GameHistory::default() }
+    }
+
+    pub fn record(&mut self, user_id: usize, color: usize, path: Vec<(usize, usize)>) {
+        self.moves.push(MoveRecord { user_id, color, path, timestamp: SystemTime::now() });
+    }
+
+    pub fn record_forfeit(&mut self, user_id: usize) {
+        self.forfeits.push((user_id, self.moves.len()));
+    }
+
+    pub fn replay(&self, ply: usize) -> std::result::Result<GameState, usize> {
+        if ply > self.moves.len() {
+            return Err(0);
+        }
+        let mut gs = GameState { rules: self.rules, teams: self.teams.clone(), ..GameState::with_board(self.board) };
+        let mut colors: Vec<(&usize, &usize)> = self.initial_colors.iter().collect();
+        colors.sort_unstable();
+        for (user_id, color) in colors {
+            gs.add_cones(*user_id, *color)?;
+        }
+        for (i, m) in self.moves.iter().take(ply).enumerate() {
+            self.remove_forfeited(&mut gs, i);
+            // Each player moves only their own cones.
+            if m.path.first().and_then(|p| gs.cones.get(p)) != Some(&m.user_id) {
+                return Err(i);
+            }
+            let path = m.path.iter().map(|(r, c)| (*r as i32, *c as i32)).collect();
+            gs.update_cones(&path, &m.user_id)?;
+        }
+        self.remove_forfeited(&mut gs, ply);
+        Ok(gs)
+    }
+
+    fn remove_forfeited(&self, gs: &mut GameState, ply: usize) {
+        for (user_id, _) in self.forfeits.iter().filter(|(_, p)| *p == ply) {
+            gs.cones.retain(|_, id| id != user_id);
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LegalMove {
+    pub from: (usize, usize),
+    pub to: (usize, usize),
+    pub path: Vec<(usize, usize)>,
+}
+
 pub fn serialize_cones<S>(cones: &HashMap<(usize, usize), usize>, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
     let mut map = serializer.serialize_map(Some(cones.len()))?;
     for ((row, col), v) in cones {
@@ -67,11 +173,26 @@ pub fn serialize_cones<S>(cones: &HashMap<(usize, usize), usize>, serializer: S)
     map.end()
 }
 
+pub fn deserialize_cones<'de, D>(deserializer: D) -> Result<HashMap<(usize, usize), usize>, D::Error> where D: Deserializer<'de> {
+    let raw: HashMap<String, usize> = HashMap::deserialize(deserializer)?;
+    let mut cones = HashMap::with_capacity(raw.len());
+    for (key, v) in raw {
+        let mut parts = key.split(',').map(|p| p.trim().parse::<usize>());
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(Ok(row)), Some(Ok(col)), None) => {
+                cones.insert((row, col), v);
+            }
+            _ => return Err(D::Error::custom(format!("invalid cone position: {}", key)))
+        }
+    }
+    Ok(cones)
+}
+
 
 impl GameState {
-    pub fn get_board_color(&self, row: &usize, col: &usize) -> std::result::Result<&'static usize, usize> {
+    pub fn get_board_color(&self, row: &usize, col: &usize) -> std::result::Result<usize, usize> {
         self.validate_dimensions(*row as i32, *col as i32)?;
-        Ok(&POINTS[*row][*col])
+        Ok(self.board.color(*row, *col))
     }
 
     pub fn add_cones(&mut self, user_id: usize, color: usize) -> std::result::Result<(), usize> {
@@ -88,12 +209,9 @@ impl GameState {
     }
 
     fn add_cones_for_user(&mut self, user_id: usize, color: usize) -> std::result::Result<(), usize> {
-        for (row, cols) in POINTS.iter().enumerate() {
-            for (col, c) in cols.iter().enumerate() {
-                if *c == color {
-                    self.add_cone(row as i32, col as i32, user_id)?;
-                }
-            }
+        let board = self.board;
+        for (row, col) in board.points().filter(|(row, col)| board.color(*row, *col) == color) {
+            self.add_cone(row as i32, col as i32, user_id)?;
         }
         Ok(())
     }
@@ -108,10 +226,7 @@ impl GameState {
         result
     }
     pub fn validate_dimensions(&self, row: i32, position: i32) -> std::result::Result<(usize, usize), usize> {
-        if row < 0 || row > 20 {
-            return Err(0);
-        }
-        if position < 0 || position >= POINT_COUNTS[row as usize] as i32 {
+        if !self.board.contains(row, position) {
             return Err(0);
         }
         Ok((row as usize, position as usize))
@@ -132,32 +247,66 @@ impl GameState {
         Ok(true)
     }
 
-    fn can_jump(&self, from: (i32, i32), to: (i32, i32)) -> std::result::Result<bool, usize> {
-        let from_neighbors = self.get_neighbors(from.0, from.1)?;
-        let to_valid = self.validate_dimensions(to.0, to.1)?;
-        // println!("From {:?}, to {:?},  Neighbors: {:?}", from, to, from_neighbors);
-        if from_neighbors.contains(&to_valid) || self.is_occupied(to.0, to.1)? {
-            //   println!("Here1");
+    // Returns the point jumped over.
+    fn can_jump(&self, from: (i32, i32), to: (i32, i32)) -> std::result::Result<(usize, usize), usize> {
+        if self.rules.super_jumps {
+            return self.can_super_jump(from, to);
+        }
+        let from = self.validate_dimensions(from.0, from.1)?;
+        let to = self.validate_dimensions(to.0, to.1)?;
+        if self.cones.contains_key(&to) {
             return Err(1);
         }
-        let mut common_neighbors = Vec::new();
-        for x in from_neighbors {
-            let nn = self.get_neighbors(x.0 as i32, x.1 as i32)?;
-            // println!("x {:?},  Neighbors: {:?}", x, nn);
-            if nn.contains(&(from.0 as usize, from.1 as usize)) && nn.contains(&(to.0 as usize, to.1 as usize)) {
-                common_neighbors.push(x);
-            }
+        // The point jumped over is the one in the middle of a straight line of three.
+        let (from, to) = (self.board.hex(from.0, from.1), self.board.hex(to.0, to.1));
+        match DIRECTIONS.iter().find(|d| from + **d * 2 == to).and_then(|d| self.board.point(from + *d)) {
+            Some(over) if self.cones.contains_key(&over) => Ok(over),
+            _ => Err(1)
         }
-        // println!("common_neighbors: {:?}", common_neighbors);
-        if common_neighbors.len() == 1 {
-            let neighbor = common_neighbors[0].clone();
-            if self.is_occupied(neighbor.0 as i32, neighbor.1 as i32)? {
-                return Ok(true);
+    }
+
+    // Checks the points on the line from `from` to `to`: the one in the middle has to be occupied and the rest empty.
+    fn can_super_jump(&self, from: (i32, i32), to: (i32, i32)) -> std::result::Result<(usize, usize), usize> {
+        let (row, col) = self.validate_dimensions(from.0, from.1)?;
+        let to = self.validate_dimensions(to.0, to.1)?;
+        for line in self.board.lines(row, col) {
+            if let Some(pos) = line.iter().position(|p| *p == to) {
+                let middle = pos / 2;
+                if pos % 2 == 1 && !self.cones.contains_key(&to)
+                    && line[..pos].iter().enumerate().all(|(i, p)| self.cones.contains_key(p) == (i == middle)) {
+                    return Ok(line[middle]);
+                }
+                break;
             }
         }
         Err(1)
     }
 
+    // A jump made by the cone that started the move at `origin`.
+    fn is_allowed_jump(&self, origin: (usize, usize), from: (i32, i32), to: (i32, i32)) -> bool {
+        self.can_jump(from, to).is_ok_and(|over| self.may_jump_over(origin, over))
+    }
+
+    fn may_jump_over(&self, origin: (usize, usize), over: (usize, usize)) -> bool {
+        if !self.rules.partner_jumps_only {
+            return true;
+        }
+        match (self.cones.get(&origin), self.cones.get(&over)) {
+            (Some(mover), Some(other)) => self.are_partners(mover, other),
+            _ => true
+        }
+    }
+
+    /// Index in `teams` of the team the user plays in.
+    pub fn team_of(&self, user_id: &usize) -> Option<usize> {
+        let color = self.players_colors.get(user_id)?;
+        self.teams.iter().position(|t| t.contains(color))
+    }
+
+    pub fn are_partners(&self, user_id: &usize, other: &usize) -> bool {
+        user_id == other || self.team_of(user_id).is_some_and(|t| self.team_of(other) == Some(t))
+    }
+
     pub fn is_all_cones_in_place(&self, user_id: &usize) -> std::result::Result<bool, usize> {
         match self.players_colors.get(user_id) {
             None => {
@@ -165,9 +314,12 @@ impl GameState {
             }
             Some(color) => {
                 let complementary_color = get_complementary(color);
+                if self.rules.spoiler && self.is_spoiled(user_id, *complementary_color) {
+                    return Ok(true);
+                }
                 for ((r, c), id) in self.cones.iter() {
                     let board_color = self.get_board_color(r, c)?;
-                    if *id == *user_id && *board_color != *complementary_color {
+                    if *id == *user_id && board_color != *complementary_color {
                         return Ok(false);
                     }
                 }
@@ -176,6 +328,34 @@ impl GameState {
         }
     }
 
+    fn is_spoiled(&self, user_id: &usize, destination: usize) -> bool {
+        let mut points = self.board.points().filter(|(r, c)| self.board.color(*r, *c) == destination);
+        let mut own = false;
+        let full = points.all(|p| match self.cones.get(&p) {
+            Some(id) => {
+                own |= id == user_id;
+                true
+            }
+            None => false
+        });
+        full && own
+    }
+
+    // Checks where the cone at `from` may end its move, the points are on the board.
+    fn is_allowed_destination(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        let color = match self.cones.get(&from).and_then(|id| self.players_colors.get(id)) {
+            Some(color) => *color,
+            None => return true
+        };
+        let destination = *get_complementary(&color);
+        let from_color = self.board.color(from.0, from.1);
+        let to_color = self.board.color(to.0, to.1);
+        if self.rules.no_parking && to_color != NEUTRAL && to_color != color && to_color != destination {
+            return false;
+        }
+        !(self.rules.no_leaving_destination && from_color == destination && to_color != destination)
+    }
+
     pub fn update_cones(&mut self, path: &Vec<(i32, i32)>, user_id: &usize) -> std::result::Result<(Vec<(usize, usize)>, bool), usize> {
         self.validate_path(&path)?;
         let (s1, s2) = path[0].clone();
@@ -198,6 +378,11 @@ impl GameState {
         if path.len() < 2 {
             return Err(0);
         }
+        let from = self.validate_dimensions(path[0].0, path[0].1)?;
+        let to = self.validate_dimensions(path[path.len() - 1].0, path[path.len() - 1].1)?;
+        if !self.is_allowed_destination(from, to) {
+            return Err(1);
+        }
         if path.len() == 2 {
             let prev = *path.get(0).unwrap();
             let next = *path.get(1).unwrap();
@@ -209,10 +394,10 @@ impl GameState {
             return if neighbors.contains(&(next.0 as usize, next.1 as usize)) {
                 // println!("shifting 1 position, ok");
                 Ok(true)
-            } else if self.can_jump(prev, next)? {
+            } else if self.is_allowed_jump(from, prev, next) {
                 Ok(true)
             } else {
-                Err(0)
+                Err(1)
             };
         }
         for (ind, (row, col)) in path.iter().enumerate() {
@@ -223,67 +408,142 @@ impl GameState {
                 return Err(1);
             }
             let prev = path[ind - 1];
-            if !self.can_jump(prev, (*row, *col))? {
+            if !self.is_allowed_jump(from, prev, (*row, *col)) {
                 return Err(1);
             }
         }
         Ok(true)
     }
 
-    fn calculate_shift(up_shift: i32, incr: bool) -> (i32, i32) {
-        let shift = if up_shift.abs() > 1 { up_shift.signum() * (up_shift.abs() - 1) / 2 } else { up_shift };
-        if shift == -1 {
-            (shift, shift + 1)
-        } else if shift == 1 {
-            (shift, shift - 1)
-        } else if shift == -5 {
-            if incr {
-                (shift, shift + 1)
-            } else {
-                (shift, shift - 1)
+    pub fn legal_moves(&self, from: (usize, usize)) -> std::result::Result<Vec<LegalMove>, usize> {
+        let origin = self.validate_dimensions(from.0 as i32, from.1 as i32)?;
+        if !self.cones.contains_key(&origin) {
+            return Err(0);
+        }
+        let mut paths: HashMap<(usize, usize), Vec<(usize, usize)>> = HashMap::new();
+        for n in self.sorted_neighbors(origin)? {
+            if !self.is_occupied(n.0 as i32, n.1 as i32)? {
+                paths.insert(n, vec![origin, n]);
             }
-        } else if shift == 5 {
-            if incr {
-                (shift, shift + 1)
-            } else {
-                (shift, shift - 1)
+        }
+        // Breadth-first search over chained jumps, so every destination gets the shortest hop sequence.
+        let mut previous: HashMap<(usize, usize), (usize, usize)> = HashMap::new();
+        let mut queue = VecDeque::new();
+        queue.push_back(origin);
+        while let Some(current) = queue.pop_front() {
+            for target in self.jump_targets(current, origin)? {
+                if target == origin || previous.contains_key(&target) {
+                    continue;
+                }
+                previous.insert(target, current);
+                queue.push_back(target);
+                paths.entry(target).or_insert_with(|| {
+                    let mut path = vec![target];
+                    let mut p = target;
+                    while let Some(prev) = previous.get(&p) {
+                        path.push(*prev);
+                        p = *prev;
+                    }
+                    path.reverse();
+                    path
+                });
             }
-        } else {
-            (-1, -1)
         }
+        let mut result: Vec<LegalMove> = paths.into_iter()
+            .filter(|(to, _)| self.is_allowed_destination(origin, *to))
+            .map(|(to, path)| LegalMove { from: origin, to, path })
+            .collect();
+        result.sort_by_key(|m| m.to);
+        Ok(result)
     }
 
-    pub fn get_neighbors(&self, row: i32, col: i32) -> std::result::Result<HashSet<(usize, usize)>, usize> {
-        let (valid_row, _) = self.validate_dimensions(row, col)?;
-        let last_row = (POINT_COUNTS.len() - 1) as i32;
-        let upper_row_points_count = if row < last_row { POINT_COUNTS[valid_row + 1] as i32 } else { -1 };
-        let lower_row_points_count = if row > 0 { POINT_COUNTS[valid_row - 1] as i32 } else { -1 };
-        let current_row_points_count = POINT_COUNTS[valid_row] as i32;
-        let up_shift: i32 = if row < last_row { upper_row_points_count - current_row_points_count } else { 1 };
-        let down_shift: i32 = if row > 0 { lower_row_points_count - current_row_points_count } else { 1 };
-        let us = GameState::calculate_shift(up_shift, row <= 10);
-        let ds = GameState::calculate_shift(down_shift, row >= 10);
-        let all_pos: [(i32, i32); 6] = [(row, col - 1), (row, col + 1), (row - 1, col + ds.0), (row - 1, col + ds.1), (row + 1, col + us.0), (row + 1, col + us.1)];
-        //println!("us: {:?}, ds: {:?}, all_pos: {:?}", us, ds, all_pos);
-        let mut result = HashSet::new();
-        for (r, c) in all_pos.iter() {
-            if self.validate_dimensions(*r, *c).is_ok() {
-                if *c != col || ((*r < valid_row as i32 && down_shift.abs() <= 1) || (*r > valid_row as i32 && up_shift.abs() <= 1)) {
-                    result.insert((*r as usize, *c as usize));
+    pub fn all_legal_moves(&self, user_id: &usize) -> std::result::Result<Vec<LegalMove>, usize> {
+        let mut cones = self.get_cones(user_id);
+        cones.sort_unstable();
+        let mut result = Vec::new();
+        for cone in cones {
+            result.extend(self.legal_moves(cone)?);
+        }
+        Ok(result)
+    }
+
+    pub fn find_path(&self, from: (i32, i32), to: (i32, i32)) -> std::result::Result<Vec<(i32, i32)>, usize> {
+        let (from_row, from_col) = self.validate_dimensions(from.0, from.1)?;
+        let destination = self.validate_dimensions(to.0, to.1)?;
+        self.legal_moves((from_row, from_col))?
+            .into_iter()
+            .find(|m| m.to == destination)
+            .map(|m| m.path.iter().map(|(r, c)| (*r as i32, *c as i32)).collect())
+            .ok_or(1)
+    }
+
+    fn sorted_neighbors(&self, point: (usize, usize)) -> std::result::Result<Vec<(usize, usize)>, usize> {
+        let mut neighbors: Vec<(usize, usize)> = self.get_neighbors(point.0 as i32, point.1 as i32)?.into_iter().collect();
+        neighbors.sort_unstable();
+        Ok(neighbors)
+    }
+
+    // The moving cone has already left `origin`, so that point can be neither jumped over nor landed on.
+    fn jump_targets(&self, from: (usize, usize), origin: (usize, usize)) -> std::result::Result<Vec<(usize, usize)>, usize> {
+        if self.rules.super_jumps {
+            return Ok(self.super_jump_targets(from, origin));
+        }
+        let from_i = (from.0 as i32, from.1 as i32);
+        let mut result = Vec::new();
+        for middle in self.sorted_neighbors(from)? {
+            if middle == origin || !self.is_occupied(middle.0 as i32, middle.1 as i32)? {
+                continue;
+            }
+            for target in self.sorted_neighbors(middle)? {
+                if target == from || target == origin || result.contains(&target) {
+                    continue;
+                }
+                if self.is_allowed_jump(origin, from_i, (target.0 as i32, target.1 as i32)) {
+                    result.push(target);
                 }
             }
         }
         Ok(result)
     }
 
+    // Lines passing the origin are skipped altogether, validate_path still sees the moving cone there.
+    fn super_jump_targets(&self, from: (usize, usize), origin: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut result = Vec::new();
+        for line in self.board.lines(from.0, from.1) {
+            let middle = match line.iter().position(|p| self.cones.contains_key(p)) {
+                Some(m) => m,
+                None => continue
+            };
+            let target = 2 * middle + 1;
+            if target < line.len()
+                && !line[..=target].contains(&origin)
+                && self.may_jump_over(origin, line[middle])
+                && line[middle + 1..=target].iter().all(|p| !self.cones.contains_key(p)) {
+                result.push(line[target]);
+            }
+        }
+        result
+    }
+
+    pub fn get_neighbors(&self, row: i32, col: i32) -> std::result::Result<HashSet<(usize, usize)>, usize> {
+        let (valid_row, valid_col) = self.validate_dimensions(row, col)?;
+        Ok(self.board.neighbors(valid_row, valid_col).into_iter().collect())
+    }
 
     pub fn new() -> GameState {
         GameState {
+            board: Board::default(),
+            rules: Rules::default(),
+            teams: Vec::new(),
             cones: Default::default(),
             players_colors: Default::default(),
             moves: Default::default(),
         }
     }
+
+    pub fn with_board(board: Board) -> GameState {
+        GameState { board, ..GameState::new() }
+    }
 }
 
 #[cfg(test)]
@@ -335,6 +595,229 @@ mod tests {
         assert!(game_state.validate_path(&vec![(1, 1), (3, 3), (5, 10), (7, 10)]).is_ok());
     }
 
+    #[test]
+    fn test_legal_moves() {
+        let mut game_state = GameState::new();
+        game_state.add_cone(3, 0, PURPLE).unwrap();
+        game_state.add_cone(4, 0, YELLOW).unwrap();
+        game_state.add_cone(3, 1, YELLOW).unwrap();
+        game_state.add_cone(5, 6, YELLOW).unwrap();
+        let moves = game_state.legal_moves((3, 0)).unwrap();
+        let destinations: Vec<(usize, usize)> = moves.iter().map(|m| m.to).collect();
+        assert_eq!(vec![(2, 0), (3, 2), (4, 1), (5, 5), (5, 7)], destinations);
+        let chained = moves.iter().find(|m| m.to == (5, 7)).unwrap();
+        assert_eq!(vec![(3, 0), (5, 5), (5, 7)], chained.path);
+        assert!(game_state.legal_moves((3, 3)).is_err());
+    }
+
+    #[test]
+    fn test_legal_moves_are_valid_paths() {
+        let mut game_state = GameState::new();
+        game_state.add_cones_for_user(0, PURPLE).unwrap();
+        game_state.add_cones_for_user(1, BLUE).unwrap();
+        game_state.remove_cone(3, 1).unwrap();
+        game_state.add_cone(6, 6, 1).unwrap();
+        let moves = game_state.all_legal_moves(&0).unwrap();
+        assert!(!moves.is_empty());
+        for m in moves.iter() {
+            let path: Vec<(i32, i32)> = m.path.iter().map(|(r, c)| (*r as i32, *c as i32)).collect();
+            assert!(game_state.validate_path(&path).is_ok(), "invalid path {:?}", m.path);
+        }
+    }
+
+    #[test]
+    fn test_find_path() {
+        let mut game_state = GameState::new();
+        game_state.add_cone(3, 0, PURPLE).unwrap();
+        game_state.add_cone(4, 0, YELLOW).unwrap();
+        game_state.add_cone(5, 6, YELLOW).unwrap();
+        assert_eq!(Ok(vec![(3, 0), (5, 5), (5, 7)]), game_state.find_path((3, 0), (5, 7)));
+        assert_eq!(Ok(vec![(3, 0), (2, 0)]), game_state.find_path((3, 0), (2, 0)));
+        assert_eq!(Err(1), game_state.find_path((3, 0), (9, 5)));
+        assert_eq!(Err(0), game_state.find_path((3, 0), (21, 0)));
+    }
+
+    #[test]
+    fn test_cones_serialization_roundtrip() {
+        let mut game_state = GameState::new();
+        game_state.add_cones(3, GREEN).unwrap();
+        let json = serde_json::to_string(&game_state).unwrap();
+        let restored: GameState = serde_json::from_str(&json).unwrap();
+        assert_eq!(game_state.cones, restored.cones);
+        assert_eq!(game_state.players_colors, restored.players_colors);
+        assert!(serde_json::from_str::<GameState>(r#"{"cones":{"1":3},"players_colors":{},"moves":[]}"#).is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let mut history = GameHistory::default();
+        history.initial_colors.insert(0, PURPLE);
+        history.initial_colors.insert(1, YELLOW);
+        history.record(0, PURPLE, vec![(4, 0), (5, 6)]);
+        history.record(1, YELLOW, vec![(16, 4), (15, 10)]);
+        history.record(0, PURPLE, vec![(3, 0), (4, 0)]);
+
+        let start = history.replay(0).unwrap();
+        assert_eq!(30, start.cones.len());
+        assert_eq!(Some(&0), start.cones.get(&(4, 0)));
+
+        let second = history.replay(2).unwrap();
+        assert_eq!(None, second.cones.get(&(4, 0)));
+        assert_eq!(Some(&0), second.cones.get(&(5, 6)));
+        assert_eq!(Some(&1), second.cones.get(&(15, 10)));
+        assert_eq!(Some(&0), second.cones.get(&(3, 0)));
+
+        let last = history.replay(3).unwrap();
+        assert_eq!(Some(&0), last.cones.get(&(4, 0)));
+        assert_eq!(None, last.cones.get(&(3, 0)));
+        assert!(history.replay(4).is_err());
+    }
+
+    #[test]
+    fn test_small_board() {
+        let mut game_state = GameState::with_board(Board::new(3).unwrap());
+        game_state.add_cones(0, PURPLE).unwrap();
+        game_state.add_cones(1, BLUE).unwrap();
+        assert_eq!(6, game_state.get_cones(&0).len());
+        assert_eq!(Some(&1), game_state.cones.get(&(3, 0)));
+        assert!(game_state.validate_dimensions(13, 0).is_err());
+        assert!(game_state.validate_dimensions(3, 10).is_err());
+        assert_eq!(Ok(HashSet::from_iter(vec![(5, 2), (5, 4), (4, 3), (4, 4), (6, 2), (6, 3)].into_iter())), game_state.get_neighbors(5, 3));
+        assert_eq!(Ok(HashSet::from_iter(vec![(2, 1), (2, 2), (3, 4), (3, 6), (4, 4), (4, 5)].into_iter())), game_state.get_neighbors(3, 5));
+        assert_eq!(Ok(false), game_state.is_all_cones_in_place(&0));
+        game_state.remove_cones(0);
+        game_state.players_colors.insert(0, PURPLE);
+        for (row, col) in (10..13).flat_map(|row| (0..13 - row).map(move |col| (row, col))) {
+            game_state.add_cone(row, col, 0).unwrap();
+        }
+        assert_eq!(Ok(true), game_state.is_all_cones_in_place(&0));
+    }
+
+    fn super_jumps() -> GameState {
+        GameState { rules: Rules { super_jumps: true, ..Rules::default() }, ..GameState::new() }
+    }
+
+    #[test]
+    fn test_super_jumps() {
+        let mut game_state = super_jumps();
+        game_state.add_cone(4, 0, PURPLE).unwrap();
+        game_state.add_cone(7, 6, YELLOW).unwrap();
+        // Out of the triangle, over rows 4 and 5 that differ by eleven points.
+        assert!(game_state.validate_path(&vec![(4, 0), (10, 6)]).is_ok());
+        assert!(game_state.validate_path(&vec![(4, 0), (9, 6)]).is_err());
+        assert!(game_state.validate_path(&vec![(4, 0), (11, 7)]).is_err());
+        let mut standard = game_state.clone();
+        standard.rules = Rules::default();
+        assert!(standard.validate_path(&vec![(4, 0), (10, 6)]).is_err());
+
+        // Over the middle row, where the shift between rows changes sides.
+        game_state.remove_cone(7, 6).unwrap();
+        game_state.add_cone(9, 6, YELLOW).unwrap();
+        assert!(game_state.validate_path(&vec![(4, 0), (14, 10)]).is_ok());
+        game_state.add_cone(12, 8, YELLOW).unwrap();
+        assert!(game_state.validate_path(&vec![(4, 0), (14, 10)]).is_err());
+
+        // Plain jumps are the shortest super jumps.
+        game_state.add_cone(12, 9, YELLOW).unwrap();
+        assert!(game_state.validate_path(&vec![(12, 8), (12, 10)]).is_ok());
+    }
+
+    #[test]
+    fn test_super_jumps_on_the_edges() {
+        let mut game_state = super_jumps();
+        // Into the yellow triangle, over rows 15 and 16.
+        game_state.add_cone(12, 3, PURPLE).unwrap();
+        game_state.add_cone(15, 6, YELLOW).unwrap();
+        assert!(game_state.validate_path(&vec![(12, 3), (18, 1)]).is_ok());
+        assert!(game_state.validate_path(&vec![(12, 3), (17, 1)]).is_err());
+        game_state.add_cone(13, 9, PURPLE).unwrap();
+        game_state.add_cone(15, 9, YELLOW).unwrap();
+        assert!(game_state.validate_path(&vec![(13, 9), (17, 2)]).is_ok());
+        assert!(game_state.validate_path(&vec![(13, 9), (17, 3)]).is_err());
+
+        // Jumps do not bend around the corners of the star.
+        game_state.add_cone(4, 0, PURPLE).unwrap();
+        game_state.add_cone(5, 5, YELLOW).unwrap();
+        assert!(game_state.validate_path(&vec![(4, 0), (5, 4)]).is_err());
+        assert!(game_state.validate_path(&vec![(4, 0), (6, 4)]).is_ok());
+    }
+
+    #[test]
+    fn test_super_jump_moves() {
+        let mut game_state = super_jumps();
+        game_state.add_cones_for_user(0, PURPLE).unwrap();
+        game_state.add_cone(8, 8, 1).unwrap();
+        game_state.add_cone(13, 11, 1).unwrap();
+        game_state.add_cone(10, 2, 1).unwrap();
+        let moves = game_state.legal_moves((4, 2)).unwrap();
+        assert_eq!(vec![(4, 2), (12, 10), (14, 12)], moves.iter().find(|m| m.to == (14, 12)).unwrap().path);
+        assert_eq!(Ok(vec![(4, 2), (12, 10)]), game_state.find_path((4, 2), (12, 10)));
+        for m in game_state.all_legal_moves(&0).unwrap() {
+            let path: Vec<(i32, i32)> = m.path.iter().map(|(r, c)| (*r as i32, *c as i32)).collect();
+            assert!(game_state.validate_path(&path).is_ok(), "invalid path {:?}", m.path);
+        }
+    }
+
+    fn with_rules(rules: Rules) -> GameState {
+        let mut game_state = GameState { rules, ..GameState::new() };
+        game_state.players_colors.insert(0, PURPLE);
+        game_state.players_colors.insert(1, YELLOW);
+        game_state
+    }
+
+    #[test]
+    fn test_no_parking() {
+        let mut game_state = with_rules(Rules { no_parking: true, ..Rules::default() });
+        game_state.add_cone(6, 10, 0).unwrap();
+        game_state.add_cone(3, 0, 0).unwrap();
+        assert!(game_state.validate_path(&vec![(6, 10), (5, 11)]).is_err());
+        assert!(game_state.validate_path(&vec![(6, 10), (6, 9)]).is_ok());
+        assert!(game_state.validate_path(&vec![(3, 0), (3, 1)]).is_ok());
+        assert!(game_state.legal_moves((6, 10)).unwrap().iter().all(|m| game_state.board.color(m.to.0, m.to.1) == NEUTRAL));
+        game_state.rules = Rules::default();
+        assert!(game_state.validate_path(&vec![(6, 10), (5, 11)]).is_ok());
+    }
+
+    #[test]
+    fn test_spoiler() {
+        let mut game_state = with_rules(Rules { spoiler: true, ..Rules::default() });
+        game_state.add_cone(3, 0, 0).unwrap();
+        for (row, col) in game_state.board.points().filter(|(r, _)| *r > 15).collect::<Vec<_>>() {
+            game_state.add_cone(row as i32, col as i32, 1).unwrap();
+        }
+        assert_eq!(Ok(false), game_state.is_all_cones_in_place(&0));
+        game_state.cones.insert((20, 0), 0);
+        assert_eq!(Ok(true), game_state.is_all_cones_in_place(&0));
+        game_state.rules = Rules::default();
+        assert_eq!(Ok(false), game_state.is_all_cones_in_place(&0));
+    }
+
+    #[test]
+    fn test_no_leaving_destination() {
+        let mut game_state = with_rules(Rules { no_leaving_destination: true, ..Rules::default() });
+        game_state.add_cone(16, 0, 0).unwrap();
+        game_state.add_cone(14, 5, 0).unwrap();
+        assert!(game_state.validate_path(&vec![(16, 0), (15, 5)]).is_err());
+        assert!(game_state.validate_path(&vec![(16, 0), (16, 1)]).is_ok());
+        assert!(game_state.validate_path(&vec![(14, 5), (15, 5)]).is_ok());
+        assert_eq!(vec![(16, 1), (17, 0)], game_state.legal_moves((16, 0)).unwrap().iter().map(|m| m.to).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_partner_jumps_only() {
+        let mut game_state = with_rules(Rules { partner_jumps_only: true, ..Rules::default() });
+        game_state.players_colors.insert(2, GREEN);
+        game_state.teams = vec![vec![PURPLE, YELLOW], vec![GREEN]];
+        game_state.add_cone(3, 0, 0).unwrap();
+        game_state.add_cone(4, 0, 2).unwrap();
+        assert!(game_state.validate_path(&vec![(3, 0), (5, 5)]).is_err());
+        assert!(game_state.legal_moves((3, 0)).unwrap().iter().all(|m| m.to != (5, 5)));
+        game_state.cones.insert((4, 0), 1);
+        assert!(game_state.validate_path(&vec![(3, 0), (5, 5)]).is_ok());
+        assert!(game_state.are_partners(&0, &1));
+        assert!(!game_state.are_partners(&0, &2));
+    }
+
     #[test]
     fn test_get_neighbors() {
         let game_state = GameState::new();
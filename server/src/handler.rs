@@ -1,3 +1,4 @@
+use std::collections::HashMap;
 use std::convert::Infallible;
 use std::error::Error;
 use std::sync::Arc;
@@ -13,11 +14,21 @@ use warp::filters::sse::ServerSentEvent;
 use warp::hyper::StatusCode;
 use warp::reply::json;
 
-use crate::{cancel_timer, HOST, PORT, Result, RoomHandle, RoomList, RoomTimersList, start_timer, User, UserTokens, ws};
-use crate::game::{GameState, NEUTRAL};
-use crate::model::{AddUserRequest, CreateRoomRequest, CreateRoomResponse, ErrorMessage, GameColorsUpdate, PlayerDesc, PublishToARoomRequest, RoomDesc, RoomFull, RoomIdParameter, RoomNotFound, RoomStateUpdate, TokenCreatedResponse, UpdateRoomStateRequest, UpdateRoomType, UserNotFound};
+use crate::{AccountList, bot, MatchQueue, RatingList, StatsList, TournamentList, cancel_timer, Result, RoomHandle, RoomList, RoomTimersList, start_timer, User, UserTokens, ws};
+use crate::game::{GameHistory, GameState, NEUTRAL};
+use crate::accounts::{Account, MIN_PASSWORD_LEN, normalize_login, verify_login};
+use crate::board::Board;
+use crate::bot::{Bot, Difficulty};
+use crate::clock::{GameClock, TimeControl, TimeoutPolicy};
+use crate::config::Config;
+use crate::model::{AccountExists, AddBotRequest, AddUserRequest, BracketResponse, CreateRoomRequest, CreateRoomResponse, CreateTournamentRequest, ErrorMessage, EventLog, GameColorsUpdate, GameStateResponse, InvalidCredentials, InvalidRecord, LeaderboardEntry, LeaderboardQuery, LeaderboardResponse, LegalMovesQuery, LoginRequest, MatchFoundUpdate, PlayerDesc, PlayerFinishedUpdate, ProfileResponse, QueueRequest, QueueResponse, RatingResponse, ReplayQuery, PublishToARoomRequest, RoomDesc, RoomFull, RoomIdParameter, RoomLinks, RoomNotFound, RoomOptions, RoomStateUpdate, SignupRequest, StatsResponse, TokenCreatedResponse, TournamentDesc, TournamentNotFound, UpdateProfileRequest, UpdateRoomStateRequest, UpdateRoomType, UserNotFound};
 use crate::model::UpdateRoomType::{ColorChange, Start, Stop};
-use crate::ws::{ChatMessage, PlayerLeftUpdate, send_update, SendMessageRequest};
+use crate::matchmaking::{MATCH_SIZES, match_colors, QueueEntry};
+use crate::ratings::ResultSender;
+use crate::stats::PlayerStats;
+use crate::tournament::{Tournament, TOURNAMENT_COLORS};
+use crate::record::GameRecord;
+use crate::ws::{ChatMessage, PlayerJoinedUpdate, PlayerLeftUpdate, send_update, SendMessageRequest};
 use std::cmp::max;
 
 pub async fn get_rooms_handler(rooms: RoomList) -> Result<impl Reply> {
@@ -38,45 +49,50 @@ pub async fn update_room_state_handler(room_id: String, body: UpdateRoomStateReq
 
 pub async fn room_chat_message_handler(room_id: String, body: SendMessageRequest, rooms: RoomList, user: Option<User>) -> Result<impl Reply> {
     if let Some(usr) = user {
-        let mut set_ready = None;
-        if let Some(_) = body.set_ready {
-            if let Ok(mut lock) = rooms.try_write() {
-                if let Some(room) = lock.get_mut(&room_id) {
-                    for player in room.players.iter_mut() {
-                        if player.user_id == usr.user_id {
-                            player.ready = true;
-                            set_ready = Some(true);
-                        }
+        send_chat_message(&room_id, &usr, body, &rooms)?;
+        Ok(StatusCode::OK)
+    } else {
+        Err(warp::reject::custom(UserNotFound))
+    }
+}
+
+pub fn send_chat_message(room_id: &str, usr: &User, body: SendMessageRequest, rooms: &RoomList) -> Result<()> {
+    let mut set_ready = None;
+    if body.set_ready.is_some() {
+        if let Ok(mut lock) = rooms.try_write() {
+            if let Some(room) = lock.get_mut(room_id) {
+                for player in room.players.iter_mut() {
+                    if player.user_id == usr.user_id {
+                        player.ready = true;
+                        set_ready = Some(true);
                     }
                 }
-            } else {
-                error!("Lock cannot be acquired to set ready for player {}", usr.user_id);
             }
+        } else {
+            error!("Lock cannot be acquired to set ready for player {}", usr.user_id);
         }
-        if body.message.is_some() || set_ready.is_some() {
-            if let Ok(lock) = rooms.try_read() {
-                if let Some(room) = lock.get(&room_id) {
-                    send_update(room, &ChatMessage::new(usr.user_name.as_str(), usr.user_id, body.message, set_ready));
-                    Ok(StatusCode::OK)
-                } else {
-                    Err(warp::reject::custom(RoomNotFound))
-                }
+    }
+    if body.message.is_some() || set_ready.is_some() {
+        if let Ok(mut lock) = rooms.try_write() {
+            if let Some(room) = lock.get_mut(room_id) {
+                send_update(room, &ChatMessage::new(usr.user_name.as_str(), usr.user_id, body.message, set_ready));
+                Ok(())
             } else {
-                error!("Lock cannot be acquired");
                 Err(warp::reject::custom(RoomNotFound))
             }
         } else {
-            Ok(StatusCode::OK)
+            error!("Lock cannot be acquired");
+            Err(warp::reject::custom(RoomNotFound))
         }
     } else {
-        Err(warp::reject::custom(UserNotFound))
+        Ok(())
     }
 }
 
 pub async fn make_a_move_handler(room_id: String, body: PublishToARoomRequest, rooms: RoomList, user_id_opt: Option<usize>, rooms_timers: RoomTimersList) -> Result<impl Reply> {
     match user_id_opt {
         Some(user_id) => {
-            make_a_move(room_id.clone(), user_id.clone(), rooms_timers, rooms, body).await?;
+            make_a_move(room_id, user_id, rooms_timers, rooms, body)?;
             Ok(StatusCode::OK)
         }
         None => Err(warp::reject::custom(UserNotFound))
@@ -118,6 +134,18 @@ pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply,
     } else if let Some(_) = err.find::<RoomFull>() {
         code = StatusCode::BAD_REQUEST;
         message = "Room room is full";
+    } else if err.find::<InvalidRecord>().is_some() {
+        code = StatusCode::BAD_REQUEST;
+        message = "Game record is invalid";
+    } else if err.find::<AccountExists>().is_some() {
+        code = StatusCode::CONFLICT;
+        message = "Login is already taken";
+    } else if err.find::<InvalidCredentials>().is_some() {
+        code = StatusCode::UNAUTHORIZED;
+        message = "Login or password is wrong";
+    } else if err.find::<TournamentNotFound>().is_some() {
+        code = StatusCode::NOT_FOUND;
+        message = "Tournament was not found";
     } else if let Some(_) = err.find::<CorsForbidden>() {
         code = StatusCode::BAD_REQUEST;
         message = "Header not allowed";
@@ -136,7 +164,7 @@ pub async fn handle_rejection(err: Rejection) -> std::result::Result<impl Reply,
     Ok(warp::reply::with_status(json, code))
 }
 
-pub async fn create_room_handler(user_id_opt: Option<usize>, body: CreateRoomRequest, rooms: RoomList) -> Result<impl Reply> {
+pub async fn create_room_handler(user_id_opt: Option<usize>, token: Option<String>, body: CreateRoomRequest, rooms: RoomList, config: Arc<Config>, results: ResultSender) -> Result<impl Reply> {
     let user_id = match user_id_opt {
         None => {
             return Err(warp::reject::reject());
@@ -144,15 +172,60 @@ pub async fn create_room_handler(user_id_opt: Option<usize>, body: CreateRoomReq
         Some(id) => { id }
     };
     let room_name = body.room_name;
-    if room_name.is_empty() || room_name.len() > 15 {
+    let time_control = body.time_control.unwrap_or(TimeControl { per_move_sec: Some(config.move_time_sec), ..TimeControl::default() });
+    let timeout_policy = body.timeout_policy.unwrap_or_default();
+    let board = body.board_size.map_or(Some(Board::default()), Board::new);
+    let teams = body.teams.unwrap_or_default();
+    if room_name.is_empty() || room_name.len() > 15 || !time_control.is_valid() || !timeout_policy.is_valid() || !is_valid_teams(&teams) {
         Err(warp::reject::reject())
-    } else {
+    } else if let Some(board) = board {
         let room_id = Uuid::new_v4().simple().to_string();
-        let room = create_room(room_id.clone(), user_id.clone(), room_name, rooms).await;
-        Ok(json(&CreateRoomResponse {
-            room,
-            url: format!("http://{}:{}/sse/{}", HOST, PORT, room_id.clone()),
-        }))
+        let options = RoomOptions { play_to_end: body.play_to_end, board, rules: body.rules, teams };
+        let room = create_room(room_id.clone(), user_id, room_name, GameClock::new(time_control, timeout_policy), options, rooms, Some(results)).await;
+        Ok(json(&room_response(room, token.as_deref(), &config)))
+    } else {
+        Err(warp::reject::reject())
+    }
+}
+
+fn room_response(room: RoomDesc, token: Option<&str>, config: &Config) -> CreateRoomResponse {
+    let links = RoomLinks::new(config, &room.id, token);
+    CreateRoomResponse {
+        url: links.sse.clone().unwrap_or_else(|| format!("{}/sse/{}", config.public_url(), room.id)),
+        room,
+        links,
+    }
+}
+
+pub async fn add_bot_handler(room_id: String, body: AddBotRequest, rooms: RoomList, user_id_opt: Option<usize>, users_counts: Arc<AtomicUsize>) -> Result<impl Reply> {
+    let user_id = user_id_opt.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    let mut lock = rooms.write().unwrap();
+    let r = lock.get_mut(&room_id).ok_or_else(|| warp::reject::custom(RoomNotFound))?;
+    if r.created_by != user_id || r.game_started {
+        error!("User {} cannot add bots to room {}", user_id, room_id);
+        return Err(warp::reject());
+    }
+    let gs = r.game_state.as_mut().ok_or_else(|| warp::reject::custom(RoomNotFound))?;
+    let color = (1..7).find(|c| !gs.players_colors.values().any(|v| *v == *c));
+    match color {
+        Some(color) if r.players.len() < 6 => {
+            let bot_id = users_counts.as_ref().fetch_add(1, Ordering::Relaxed);
+            let bot = Bot::new(body.difficulty.unwrap_or(Difficulty::Medium), body.seed.unwrap_or_else(rand::random));
+            let name = body.name.filter(|n| !n.is_empty() && n.len() <= 15)
+                .unwrap_or_else(|| format!("Bot {:?}", bot.difficulty));
+            if gs.add_cones(bot_id, color).is_err() {
+                error!("Error while adding cones for bot {}", bot_id);
+                return Err(warp::reject());
+            }
+            info!("Bot {} with seed {} joined room {}", bot_id, bot.seed, room_id);
+            let cones = gs.get_cones(&bot_id);
+            let player = Bot::player(bot_id, name.clone(), bot);
+            let desc = PlayerDesc::from_player(&player, color, gs.team_of(&bot_id));
+            r.players.push(player);
+            send_update(r, &PlayerJoinedUpdate::new(bot_id, room_id.clone(), cones, name, color, true));
+            Ok(json(&desc))
+        }
+        _ => Err(warp::reject::custom(RoomFull))
     }
 }
 
@@ -161,7 +234,7 @@ pub async fn get_players(query: RoomIdParameter, rooms: RoomList) -> Result<impl
     rooms.read().unwrap().get(&room_id)
         .map(|room| {
             room.players.iter().map(|player| {
-                PlayerDesc::from_player(player, room.game_state.as_ref().map(|gs| { gs.players_colors.get(&player.user_id).cloned() }).flatten().unwrap())
+                PlayerDesc::from_player(player, room.game_state.as_ref().and_then(|gs| gs.players_colors.get(&player.user_id).cloned()).unwrap(), room.team_of(player.user_id))
             }).collect()
         })
         .map(|players: Vec<PlayerDesc>| { json(&players) })
@@ -188,47 +261,404 @@ pub async fn validate_path(room_id: String, rooms: RoomList, _userid: Option<usi
     }
 }
 
+pub async fn get_legal_moves(room_id: String, query: LegalMovesQuery, rooms: RoomList, user_id_opt: Option<usize>) -> Result<impl Reply> {
+    let lock = rooms.read().unwrap();
+    let gs = lock.get(&room_id)
+        .and_then(|room| room.game_state.as_ref())
+        .ok_or_else(|| warp::reject::custom(RoomNotFound))?;
+    let moves = match (query.row, query.col, user_id_opt) {
+        (Some(row), Some(col), _) => gs.legal_moves((row, col)),
+        (None, None, Some(user_id)) => gs.all_legal_moves(&user_id),
+        (None, None, None) => return Err(warp::reject::custom(UserNotFound)),
+        _ => Err(0)
+    };
+    moves.map(|m| json(&m))
+        .map_err(|_e| {
+            error!("Could not calculate moves in room {} for {:?}", room_id, (query.row, query.col));
+            warp::reject()
+        })
+}
+
+pub async fn get_history(room_id: String, rooms: RoomList) -> Result<impl Reply> {
+    rooms.read().unwrap().get(&room_id)
+        .map(|room| json(&room.history))
+        .ok_or_else(|| warp::reject::custom(RoomNotFound))
+}
+
+pub async fn replay_handler(room_id: String, query: ReplayQuery, rooms: RoomList) -> Result<impl Reply> {
+    let lock = rooms.read().unwrap();
+    let history = &lock.get(&room_id).ok_or_else(|| warp::reject::custom(RoomNotFound))?.history;
+    history.replay(query.ply.unwrap_or(history.moves.len()))
+        .map(|gs| json(&gs))
+        .map_err(|_e| {
+            error!("Could not replay room {} up to ply {:?}", room_id, query.ply);
+            warp::reject()
+        })
+}
+
+pub async fn get_record(room_id: String, rooms: RoomList) -> Result<impl Reply> {
+    let lock = rooms.read().unwrap();
+    let room = lock.get(&room_id).ok_or_else(|| warp::reject::custom(RoomNotFound))?;
+    let reply = warp::reply::with_header(GameRecord::from_room(room).to_string(), "Content-Type", "text/plain; charset=utf-8");
+    Ok(warp::reply::with_header(reply, "Content-Disposition", format!("attachment; filename=\"{}.txt\"", room_id)))
+}
+
+pub async fn import_record_handler(user_id_opt: Option<usize>, token: Option<String>, body: warp::hyper::body::Bytes, rooms: RoomList, config: Arc<Config>) -> Result<impl Reply> {
+    let user_id = user_id_opt.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    let text = std::str::from_utf8(&body).map_err(|_e| warp::reject::custom(InvalidRecord))?;
+    let record = GameRecord::parse(text).map_err(|e| {
+        error!("Could not parse game record, line {}: {}", e.line, e.message);
+        warp::reject::custom(InvalidRecord)
+    })?;
+    let history = record.to_history();
+    let game_state = history.replay(history.moves.len()).map_err(|_e| {
+        error!("Game record contains invalid moves.");
+        warp::reject::custom(InvalidRecord)
+    })?;
+    let room_name: String = if record.room_name.is_empty() { "Imported game".to_string() } else { record.room_name.chars().take(15).collect() };
+    let room_id = Uuid::new_v4().simple().to_string();
+    // Imported games are not rated.
+    create_room(room_id.clone(), user_id, room_name, GameClock::default(), RoomOptions::default(), rooms.clone(), None).await;
+    let mut lock = rooms.write().unwrap();
+    let room = lock.get_mut(&room_id).ok_or_else(|| warp::reject::custom(RoomNotFound))?;
+    room.game_state = Some(game_state);
+    room.history = history;
+    room.winner = record.winner();
+    room.game_started = true;
+    room.game_finished = true;
+    Ok(json(&room_response(RoomDesc::from_room(room), token.as_deref(), &config)))
+}
+
 pub async fn get_game_state(query: RoomIdParameter, rooms: RoomList) -> Result<impl Reply> {
     let room_id = query.room_id;
     rooms.read().unwrap().get(&room_id)
-        .map(|room| { room.game_state.as_ref() })
-        .flatten()
-        .map(|ogs| { json(ogs) })
+        .and_then(|room| { room.game_state.as_ref().map(|gs| GameStateResponse { game: gs, clock: &room.clock }) })
+        .map(|ogs| { json(&ogs) })
         .ok_or(warp::reject::reject())
 }
 
-pub async fn refresh_token_handle(user: Option<User>, tokens: UserTokens) -> Result<impl Reply> {
-    match user {
+pub async fn refresh_token_handle(token: Option<String>, tokens: UserTokens, accounts: AccountList) -> Result<impl Reply> {
+    match token.and_then(|t| tokens.verify(&t)) {
         None => {
             Err(warp::reject::reject())
         }
-        Some(usr) => {
-            let token = Uuid::new_v4().hyphenated().to_string();
-            let user_name = usr.user_name.clone();
-            let user_id = usr.user_id.clone();
-            tokens.write().unwrap().insert(token.clone(), usr);
-            Ok(warp::reply::json(&TokenCreatedResponse { token, created_at: Instant::now(), user_id, user_name }))
+        Some(claims) => {
+            // The old token is replaced, registered users get their current profile into the new one.
+            tokens.revoke(&claims);
+            let usr = accounts.read().unwrap().values()
+                .find(|a| a.user_id == claims.uid)
+                .map(Account::user)
+                .unwrap_or_else(|| claims.user());
+            Ok(warp::reply::json(&issue_token(&tokens, usr)))
         }
     }
 }
 
+pub async fn logout_handler(token: Option<String>, tokens: UserTokens) -> Result<impl Reply> {
+    let claims = token.and_then(|t| tokens.verify(&t)).ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    tokens.revoke(&claims);
+    info!("User {} logged out.", claims.uid);
+    Ok(StatusCode::OK)
+}
+
 pub async fn add_user_handle(request: AddUserRequest, users: UserTokens, users_counts: Arc<AtomicUsize>) -> Result<impl Reply> {
-    if request.name.is_empty() || request.name.len() > 15 {
+    if !is_valid_name(&request.name) {
         Err(warp::reject())
     } else {
-        let token = Uuid::new_v4().hyphenated().to_string();
         let new_id = users_counts.as_ref().fetch_add(1, Ordering::Relaxed);
-        users.write().unwrap().insert(token.clone(), User { user_id: new_id.clone(), user_name: request.name.clone() });
-        Ok(warp::reply::json(&TokenCreatedResponse { token, created_at: Instant::now(), user_id: new_id, user_name: request.name }))
+        Ok(warp::reply::json(&issue_token(&users, User { user_id: new_id, user_name: request.name, preferred_color: None })))
+    }
+}
+
+pub async fn signup_handler(request: SignupRequest, accounts: AccountList, users: UserTokens, users_counts: Arc<AtomicUsize>) -> Result<impl Reply> {
+    let login = normalize_login(&request.login).ok_or_else(warp::reject)?;
+    let display_name = request.display_name.unwrap_or_else(|| login.clone());
+    if !is_valid_name(&display_name) || request.password.chars().count() < MIN_PASSWORD_LEN || !is_valid_color(request.preferred_color) {
+        return Err(warp::reject());
+    }
+    if accounts.read().unwrap().contains_key(&login) {
+        return Err(warp::reject::custom(AccountExists));
+    }
+    // Hashing is slow on purpose, it is done before taking the lock.
+    let new_id = users_counts.as_ref().fetch_add(1, Ordering::Relaxed);
+    let mut account = Account::new(new_id, login.clone(), &request.password, display_name);
+    account.preferred_color = request.preferred_color;
+    let user = account.user();
+    {
+        let mut lock = accounts.write().unwrap();
+        if lock.contains_key(&login) {
+            return Err(warp::reject::custom(AccountExists));
+        }
+        lock.insert(login, account);
+    }
+    info!("Registered user {}", user.user_id);
+    Ok(warp::reply::json(&issue_token(&users, user)))
+}
+
+pub async fn login_handler(request: LoginRequest, accounts: AccountList, users: UserTokens) -> Result<impl Reply> {
+    let account = normalize_login(&request.login)
+        .and_then(|login| accounts.read().unwrap().get(&login).cloned());
+    let valid = verify_login(account.as_ref(), &request.password);
+    let user = account.filter(|_| valid)
+        .map(|account| account.user())
+        .ok_or_else(|| warp::reject::custom(InvalidCredentials))?;
+    Ok(warp::reply::json(&issue_token(&users, user)))
+}
+
+pub async fn get_profile(user: Option<User>, accounts: AccountList) -> Result<impl Reply> {
+    let usr = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    accounts.read().unwrap().values()
+        .find(|a| a.user_id == usr.user_id)
+        .map(|a| json(&ProfileResponse::from_account(a)))
+        .ok_or_else(|| warp::reject::custom(UserNotFound))
+}
+
+// Tokens issued before the update keep the old name and color until they are refreshed.
+pub async fn update_profile(user: Option<User>, request: UpdateProfileRequest, accounts: AccountList) -> Result<impl Reply> {
+    let usr = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    if !request.display_name.as_ref().map_or(true, |n| is_valid_name(n)) || !is_valid_color(request.preferred_color) {
+        return Err(warp::reject());
+    }
+    let mut lock = accounts.write().unwrap();
+    let account = lock.values_mut()
+        .find(|a| a.user_id == usr.user_id)
+        .ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    if let Some(name) = request.display_name {
+        account.display_name = name;
+    }
+    if request.preferred_color.is_some() {
+        account.preferred_color = request.preferred_color;
     }
+    Ok(json(&ProfileResponse::from_account(account)))
 }
 
+pub async fn get_rating(user_id: usize, ratings: RatingList) -> Result<impl Reply> {
+    Ok(json(&RatingResponse::from_rating(&ratings.read().unwrap().get(user_id))))
+}
+
+pub async fn get_rating_history(user_id: usize, ratings: RatingList) -> Result<impl Reply> {
+    Ok(json(&ratings.read().unwrap().get(user_id).history))
+}
 
-async fn create_room(room_id: String, user_id: usize, room_name: String, rooms: RoomList) -> RoomDesc {
+pub async fn join_queue(user: Option<User>, request: QueueRequest, queue: MatchQueue, rooms: RoomList, ratings: RatingList, results: ResultSender, config: Arc<Config>) -> Result<impl Reply> {
+    let user = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    let range_valid = match (request.min_rating, request.max_rating) {
+        (Some(min), Some(max)) => min <= max,
+        _ => true
+    };
+    if !MATCH_SIZES.contains(&request.players) || !range_valid {
+        return Err(warp::reject::reject());
+    }
+    let user_id = user.user_id;
+    let entry = QueueEntry {
+        rating: ratings.read().unwrap().get(user_id).rating,
+        user,
+        players: request.players,
+        min_rating: request.min_rating.map(|r| r as f64),
+        max_rating: request.max_rating.map(|r| r as f64),
+    };
+    let matched = queue.write().unwrap().join(entry);
+    let group = match matched {
+        Some(group) => group,
+        None => {
+            let position = queue.read().unwrap().position(user_id);
+            info!("User {} is waiting for a game of {}, position {:?}.", user_id, request.players, position);
+            return Ok(json(&QueueResponse { position, room_id: None }));
+        }
+    };
+    let players: Vec<usize> = group.iter().map(|e| e.user.user_id).collect();
+    let colors = match_colors(group.len());
+    let room_id = create_private_room("Quick game".to_string(), &players, colors, &rooms, &results, &config).await;
+    info!("Matched users {:?} in room {}.", players, room_id);
+    let mut lock = queue.write().unwrap();
+    for (id, color) in players.iter().zip(colors.iter()) {
+        lock.notify(*id, &MatchFoundUpdate::new(room_id.clone(), *color, players.clone(), RoomLinks::new(&config, &room_id, None)));
+    }
+    Ok(json(&QueueResponse { position: None, room_id: Some(room_id) }))
+}
+
+/// Creates a room only the given players can sit in, each with a color of `colors` in turn.
+async fn create_private_room(room_name: String, players: &[usize], colors: &[usize], rooms: &RoomList, results: &ResultSender, config: &Config) -> String {
+    let room_id = Uuid::new_v4().simple().to_string();
+    let clock = GameClock::new(TimeControl { per_move_sec: Some(config.move_time_sec), ..TimeControl::default() }, TimeoutPolicy::default());
+    let created_by = players.first().cloned().unwrap_or_default();
+    create_room(room_id.clone(), created_by, room_name, clock, RoomOptions::default(), rooms.clone(), Some(results.clone())).await;
+    if let Some(room) = rooms.write().unwrap().get_mut(&room_id) {
+        room.private = true;
+        room.reserved = players.iter().cloned().zip(colors.iter().cloned()).collect();
+        if let Some(gs) = room.game_state.as_mut() {
+            for (id, color) in room.reserved.iter() {
+                if gs.add_cones(*id, *color).is_err() {
+                    error!("Could not add cones of user {} to room {}", id, room_id);
+                }
+            }
+        }
+    }
+    room_id
+}
+
+pub async fn create_tournament(user: Option<User>, request: CreateTournamentRequest, tournaments: TournamentList) -> Result<impl Reply> {
+    let user = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    if request.name.trim().is_empty() || request.name.len() > 30 || request.rounds == Some(0) {
+        return Err(warp::reject::reject());
+    }
+    let id = Uuid::new_v4().simple().to_string();
+    let tournament = Tournament::new(id.clone(), request.name, request.format, user.user_id, request.rounds);
+    let desc = TournamentDesc::from_tournament(&tournament);
+    tournaments.write().unwrap().insert(id, tournament);
+    Ok(json(&desc))
+}
+
+pub async fn get_tournaments(tournaments: TournamentList) -> Result<impl Reply> {
+    let list: Vec<TournamentDesc> = tournaments.read().unwrap().values().map(TournamentDesc::from_tournament).collect();
+    Ok(json(&list))
+}
+
+pub async fn get_tournament(id: String, tournaments: TournamentList) -> Result<impl Reply> {
+    match tournaments.read().unwrap().get(&id) {
+        Some(t) => Ok(json(t)),
+        None => Err(warp::reject::custom(TournamentNotFound))
+    }
+}
+
+pub async fn get_tournament_standings(id: String, tournaments: TournamentList) -> Result<impl Reply> {
+    match tournaments.read().unwrap().get(&id) {
+        Some(t) => Ok(json(&t.standings())),
+        None => Err(warp::reject::custom(TournamentNotFound))
+    }
+}
+
+pub async fn get_bracket(id: String, tournaments: TournamentList) -> Result<impl Reply> {
+    match tournaments.read().unwrap().get(&id) {
+        Some(t) => Ok(json(&BracketResponse::from_tournament(t))),
+        None => Err(warp::reject::custom(TournamentNotFound))
+    }
+}
+
+pub async fn register_tournament(id: String, user: Option<User>, tournaments: TournamentList) -> Result<impl Reply> {
+    let user = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    let mut lock = tournaments.write().unwrap();
+    let tournament = lock.get_mut(&id).ok_or_else(|| warp::reject::custom(TournamentNotFound))?;
+    if tournament.register(user.user_id, user.user_name) {
+        Ok(json(&TournamentDesc::from_tournament(tournament)))
+    } else {
+        Err(warp::reject::reject())
+    }
+}
+
+pub async fn start_tournament(id: String, user_id_opt: Option<usize>, tournaments: TournamentList, ratings: RatingList, rooms: RoomList, results: ResultSender, config: Arc<Config>) -> Result<impl Reply> {
+    let user_id = user_id_opt.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    {
+        let mut lock = tournaments.write().unwrap();
+        let tournament = lock.get_mut(&id).ok_or_else(|| warp::reject::custom(TournamentNotFound))?;
+        let ratings = ratings.read().unwrap();
+        if tournament.created_by != user_id || !tournament.start(|id| ratings.get(id).rating) {
+            return Err(warp::reject::reject());
+        }
+    }
+    info!("Tournament {} started.", id);
+    seat_tournament_games(&id, &tournaments, &rooms, &results, &config).await;
+    get_tournament(id, tournaments).await
+}
+
+/// Opens rooms for the games of the current round that have none yet.
+pub async fn seat_tournament_games(id: &str, tournaments: &TournamentList, rooms: &RoomList, results: &ResultSender, config: &Config) {
+    let (name, round, games) = match tournaments.read().unwrap().get(id) {
+        Some(t) => (t.name.chars().take(11).collect::<String>(), t.rounds.len(), t.unseated()),
+        None => return
+    };
+    for (index, players) in games {
+        let room_id = create_private_room(format!("{} R{}", name, round), &players, &TOURNAMENT_COLORS, rooms, results, config).await;
+        info!("Tournament {} round {}: users {:?} play in room {}.", id, round, players, room_id);
+        if let Some(t) = tournaments.write().unwrap().get_mut(id) {
+            t.seat(index, room_id);
+        }
+    }
+}
+
+pub async fn leave_queue(user_id_opt: Option<usize>, queue: MatchQueue) -> Result<impl Reply> {
+    let user_id = user_id_opt.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    if queue.write().unwrap().leave(user_id) {
+        Ok(StatusCode::OK)
+    } else {
+        Err(warp::reject::not_found())
+    }
+}
+
+pub async fn queue_sse_handler(user: Option<User>, queue: MatchQueue) -> Result<impl Reply> {
+    let user = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    let stream = ws::queue_connection(user, &queue)?;
+    Ok(warp::sse::reply(warp::sse::keep_alive().stream(stream)))
+}
+
+pub async fn get_stats(user_id: usize, stats: StatsList, ratings: RatingList) -> Result<impl Reply> {
+    let rating = ratings.read().unwrap().get(user_id);
+    let response = match stats.read().unwrap().get(user_id) {
+        Some(s) => StatsResponse::new(s, &rating),
+        None => StatsResponse::new(&PlayerStats::new(user_id), &rating)
+    };
+    Ok(json(&response))
+}
+
+const LEADERBOARD_PAGE_SIZE: usize = 20;
+const MAX_LEADERBOARD_PAGE_SIZE: usize = 100;
+
+// Players are ranked by rating, only those with rated games are listed.
+pub async fn get_leaderboard(query: LeaderboardQuery, ratings: RatingList, stats: StatsList) -> Result<impl Reply> {
+    let page = query.page.unwrap_or(1).max(1);
+    let per_page = query.per_page.unwrap_or(LEADERBOARD_PAGE_SIZE).clamp(1, MAX_LEADERBOARD_PAGE_SIZE);
+    let ratings = ratings.read().unwrap();
+    let stats = stats.read().unwrap();
+    let mut ranked: Vec<_> = ratings.all().filter(|r| r.games > 0).collect();
+    ranked.sort_by(|a, b| b.rating.total_cmp(&a.rating).then(a.user_id.cmp(&b.user_id)));
+    let entries = ranked.iter().enumerate()
+        .skip((page - 1).saturating_mul(per_page))
+        .take(per_page)
+        .map(|(i, r)| {
+            let s = stats.get(r.user_id);
+            LeaderboardEntry {
+                rank: i + 1,
+                user_id: r.user_id,
+                name: s.and_then(|s| s.name.clone()),
+                rating: r.rating.round() as i64,
+                games: r.games,
+                wins: s.map(|s| s.wins).unwrap_or(0),
+            }
+        })
+        .collect();
+    Ok(json(&LeaderboardResponse { page, per_page, total: ranked.len(), entries }))
+}
+
+fn issue_token(users: &UserTokens, user: User) -> TokenCreatedResponse {
+    let (token, claims) = users.issue(&user);
+    TokenCreatedResponse { token, created_at: Instant::now(), user_id: user.user_id, user_name: user.user_name, expires_at: claims.exp }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.len() <= 15
+}
+
+fn is_valid_color(color: Option<usize>) -> bool {
+    color.map_or(true, |c| c > 0 && c < 7)
+}
+
+// Teams are given as colors, each color plays in one team at most. No teams at all is fine too.
+fn is_valid_teams(teams: &[Vec<usize>]) -> bool {
+    let colors: Vec<usize> = teams.iter().flatten().cloned().collect();
+    teams.is_empty() || (teams.len() > 1
+        && teams.iter().all(|t| !t.is_empty())
+        && colors.iter().all(|c| is_valid_color(Some(*c)))
+        && colors.iter().enumerate().all(|(i, c)| !colors[..i].contains(c)))
+}
+
+
+async fn create_room(room_id: String, user_id: usize, room_name: String, clock: GameClock, options: RoomOptions, rooms: RoomList, results: Option<ResultSender>) -> RoomDesc {
+    let gs = GameState { rules: options.rules, teams: options.teams, ..GameState::with_board(options.board) };
     let handle = RoomHandle {
         winner: None,
         room_id: room_id.clone(),
         players: Vec::new(),
+        spectators: Vec::new(),
         name: room_name,
         active_player: 0,
         created_by: user_id,
@@ -236,7 +666,17 @@ async fn create_room(room_id: String, user_id: usize, room_name: String, rooms:
         game_finished: false,
         created_time: Instant::now(),
         last_updated: Instant::now(),
-        game_state: Some(GameState::new()),
+        history: GameHistory::new(&gs),
+        game_state: Some(gs),
+        events: EventLog::default(),
+        clock,
+        forfeited: Vec::new(),
+        walked_out: Vec::new(),
+        results,
+        play_to_end: options.play_to_end,
+        standings: Vec::new(),
+        private: false,
+        reserved: HashMap::new(),
     };
     let desc = RoomDesc::from_room(&handle);
     rooms.write().unwrap()
@@ -244,7 +684,7 @@ async fn create_room(room_id: String, user_id: usize, room_name: String, rooms:
     desc
 }
 
-async fn make_a_move(room_id: String, user_id: usize, rooms_timers: RoomTimersList, rooms: RoomList, request: PublishToARoomRequest) -> Result<&'static str> {
+pub fn make_a_move(room_id: String, user_id: usize, rooms_timers: RoomTimersList, rooms: RoomList, request: PublishToARoomRequest) -> Result<&'static str> {
     info!("Make a move, room: {}, user_id: {}, message: {:?}", room_id, user_id, request);
     if request.path.len() < 2 {
         error!("Path too short.");
@@ -258,20 +698,23 @@ async fn make_a_move(room_id: String, user_id: usize, rooms_timers: RoomTimersLi
         }
     }
     let transformed: Vec<(i32, i32)> = request.path.iter().map(|v| { (v[0], v[1]) }).collect();
-    if let Some(r) = rooms.clone().try_write().unwrap().get_mut(&room_id) {
+    if let Some(r) = rooms.clone().write().unwrap().get_mut(&room_id) {
         info!("Found the room: {}, created_by {} at {:?}", r.name, r.created_by, r.created_time);
         for (ind, player) in r.players.iter().enumerate() {
             info!("Looking at player (user_id: {}, number {}), current turn is: {}", player.user_id, ind, r.active_player);
             if user_id == player.user_id && ind == r.active_player {
                 info!("Player {} can make a move.", ind);
-                match r.make_a_move(transformed, user_id) {
+                let finished_before = r.standings.len();
+                match r.make_a_move(transformed, user_id, request.calculate_path) {
                     Ok(msg) => {
                         if !msg.game_finished {
-                            start_timer(rooms, rooms_timers, 30, room_id);
+                            start_timer(rooms.clone(), rooms_timers.clone(), room_id.clone());
+                            bot::schedule_bot_move(rooms, rooms_timers, r);
                         } else {
                             cancel_timer(rooms_timers, room_id);
                         }
                         send_update(r, &msg);
+                        send_player_finished(r, finished_before);
                         r.last_updated = Instant::now();
                         break;
                     }
@@ -290,7 +733,15 @@ async fn make_a_move(room_id: String, user_id: usize, rooms_timers: RoomTimersLi
     Ok("ok")
 }
 
-async fn update_room_state(room_id: String, user_id: usize, rooms: RoomList, request: UpdateRoomStateRequest, rooms_timers: RoomTimersList) {
+/// Announces the places taken since the standings had `finished_before` players.
+pub fn send_player_finished(r: &mut RoomHandle, finished_before: usize) {
+    let finished: Vec<(usize, usize)> = r.places().into_iter().take(r.standings.len()).skip(finished_before).collect();
+    for (user_id, place) in finished {
+        send_update(r, &PlayerFinishedUpdate::new(user_id, place));
+    }
+}
+
+pub async fn update_room_state(room_id: String, user_id: usize, rooms: RoomList, request: UpdateRoomStateRequest, rooms_timers: RoomTimersList) {
     info!("Update room state: {}, user_id: {}, message: {:?}", room_id, user_id, request);
     if let Some(r) = rooms.clone().write().unwrap().get_mut(&room_id) {
         info!("Found the room: {}, created_by {} at {:?}", r.name, r.created_by, r.created_time);
@@ -299,9 +750,12 @@ async fn update_room_state(room_id: String, user_id: usize, rooms: RoomList, req
                 if r.created_by == user_id && r.players.iter().all(|p| { p.ready }) {
                     r.game_started = request.update_type == Start;
                     if r.game_started {
-                        start_timer(rooms.clone(), rooms_timers, 30, room_id);
+                        r.clock.start(r.players.iter().map(|p| p.user_id));
+                        start_timer(rooms.clone(), rooms_timers.clone(), room_id.clone());
+                        bot::schedule_bot_move(rooms.clone(), rooms_timers, r);
                     }
-                    send_update(r, &RoomStateUpdate::new(r));
+                    let update = RoomStateUpdate::new(r);
+                    send_update(r, &update);
                 } else {
                     error!("Cannot start game for room {:?}.", r)
                 }
@@ -317,11 +771,15 @@ async fn update_room_state(room_id: String, user_id: usize, rooms: RoomList, req
                                         match gs.add_cones(user_id, new_color) {
                                             Ok(_) => {
                                                 let new_gs = GameState {
+                                                    board: gs.board,
+                                                    rules: gs.rules,
+                                                    teams: gs.teams.clone(),
                                                     cones: gs.cones.clone(),
                                                     players_colors: gs.players_colors.clone(),
                                                     moves: gs.moves.clone(),
                                                 };
-                                                let update = GameColorsUpdate::new(r.room_id.as_str(), new_gs);
+                                                let room_id = r.room_id.clone();
+                                                let update = GameColorsUpdate::new(room_id.as_str(), new_gs);
                                                 send_update(r, &update);
                                             }
                                             Err(_) => {
@@ -337,6 +795,11 @@ async fn update_room_state(room_id: String, user_id: usize, rooms: RoomList, req
                     });
                 }
             }
+            UpdateRoomType::Leave if r.spectators.iter().any(|s| s.user_id == user_id) => {
+                r.spectators.retain(|s| s.user_id != user_id);
+                let update = RoomStateUpdate::new(r);
+                send_update(r, &update);
+            }
             UpdateRoomType::Leave => {
                 let mut player_color = NEUTRAL;
                 r.players.retain(|p| { p.user_id != user_id });
@@ -349,6 +812,7 @@ async fn update_room_state(room_id: String, user_id: usize, rooms: RoomList, req
                 }
                 r.active_player %= max(r.players.len(), 1);
                 send_update(r, &PlayerLeftUpdate::new(user_id, room_id.clone(), r.active_player, !r.game_started, player_color));
+                bot::schedule_bot_move(rooms.clone(), rooms_timers, r);
             }
         }
     }
@@ -359,10 +823,27 @@ pub async fn health_handler() -> Result<impl Reply> {
     Ok(StatusCode::OK)
 }
 
-pub fn sse_handler(room_id: String, user: Option<User>, rooms: RoomList) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent + Send + 'static, warp::Error>> + Send + 'static> {
+pub async fn socket_handler(room_id: String, user: Option<User>, rooms: RoomList, rooms_timers: RoomTimersList, socket: warp::ws::Ws) -> Result<impl Reply> {
+    let usr = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    let (sender, receiver) = match rooms.write().unwrap().get_mut(&room_id) {
+        Some(r) => ws::join_room(room_id.clone(), usr.clone(), r, None)?,
+        None => return Err(warp::reject::custom(RoomNotFound))
+    };
+    Ok(socket.on_upgrade(move |s| ws::socket_connection(s, sender, receiver, room_id, usr, rooms, rooms_timers)))
+}
+
+pub fn spectator_sse_handler(room_id: String, user: Option<User>, rooms: RoomList, last_event_id: Option<u64>) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent, warp::Error>> + Send + 'static> {
+    let usr = user.ok_or_else(|| warp::reject::custom(UserNotFound))?;
+    match rooms.write().unwrap().get_mut(&room_id) {
+        Some(r) => ws::spectator_connection(usr, r, last_event_id),
+        None => Err(warp::reject::custom(RoomNotFound))
+    }
+}
+
+pub fn sse_handler(room_id: String, user: Option<User>, rooms: RoomList, last_event_id: Option<u64>) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent, warp::Error>> + Send + 'static> {
     if let Some(r) = rooms.clone().write().unwrap().get_mut(&room_id) {
         if let Some(usr) = user {
-            ws::client_connection(room_id, usr, r)
+            ws::client_connection(room_id, usr, r, last_event_id)
         } else {
             Err(warp::reject::custom(UserNotFound))
         }
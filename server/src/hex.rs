@@ -0,0 +1,110 @@
+use std::ops::{Add, Mul, Neg, Sub};
+
+/// A point of the board in axial coordinates, with the center of the board at `(0, 0)`.
+/// `r` grows down the rows, `q` to the right along a row, the third cube coordinate is `s = -q - r`.
+///
+/// The wire format stays `(row, col)`, see `Board::hex` and `Board::point` for the conversion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hex {
+    pub q: i32,
+    pub r: i32,
+}
+
+/// Steps to the six neighbours: left, right, up left, up right, down left and down right.
+pub const DIRECTIONS: [Hex; 6] = [
+    Hex { q: -1, r: 0 },
+    Hex { q: 1, r: 0 },
+    Hex { q: 0, r: -1 },
+    Hex { q: 1, r: -1 },
+    Hex { q: -1, r: 1 },
+    Hex { q: 0, r: 1 },
+];
+
+impl Hex {
+    pub fn new(q: i32, r: i32) -> Hex {
+        Hex { q, r }
+    }
+
+    pub fn s(&self) -> i32 {
+        -self.q - self.r
+    }
+
+    pub fn neighbors(&self) -> impl Iterator<Item=Hex> + '_ {
+        DIRECTIONS.iter().map(move |d| *self + *d)
+    }
+
+    /// Number of steps between two points on an unbounded grid.
+    pub fn distance(&self, other: Hex) -> i32 {
+        let d = *self - other;
+        (d.q.abs() + d.r.abs() + d.s().abs()) / 2
+    }
+
+    /// Rotates the point around the center by `times` sixths of a turn clockwise.
+    pub fn rotate(&self, times: i32) -> Hex {
+        (0..times.rem_euclid(6)).fold(*self, |h, _| Hex::new(-h.r, -h.s()))
+    }
+
+    /// Size of the largest of the three cube coordinates, 0 at the center.
+    pub fn radius(&self) -> i32 {
+        self.q.abs().max(self.r.abs()).max(self.s().abs())
+    }
+}
+
+impl Add for Hex {
+    type Output = Hex;
+
+    fn add(self, other: Hex) -> Hex {
+        Hex::new(self.q + other.q, self.r + other.r)
+    }
+}
+
+impl Sub for Hex {
+    type Output = Hex;
+
+    fn sub(self, other: Hex) -> Hex {
+        Hex::new(self.q - other.q, self.r - other.r)
+    }
+}
+
+impl Mul<i32> for Hex {
+    type Output = Hex;
+
+    fn mul(self, k: i32) -> Hex {
+        Hex::new(self.q * k, self.r * k)
+    }
+}
+
+impl Neg for Hex {
+    type Output = Hex;
+
+    fn neg(self) -> Hex {
+        Hex::new(-self.q, -self.r)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_directions() {
+        let origin = Hex::new(0, 0);
+        assert!(origin.neighbors().all(|h| origin.distance(h) == 1 && h.radius() == 1));
+        assert_eq!(Hex::new(1, -1), Hex::new(0, -1).rotate(1));
+        for d in DIRECTIONS.iter() {
+            assert_eq!(*d, d.rotate(6));
+            assert_eq!(-*d, d.rotate(3));
+            assert!(DIRECTIONS.contains(&d.rotate(1)));
+        }
+    }
+
+    #[test]
+    fn test_distance() {
+        let a = Hex::new(2, -3);
+        let b = Hex::new(-1, 2);
+        assert_eq!(5, a.distance(b));
+        assert_eq!(a.distance(b), a.rotate(2).distance(b.rotate(2)));
+        assert_eq!(4, (DIRECTIONS[5] * 4).distance(Hex::new(0, 0)));
+        assert_eq!(0, a.distance(a));
+    }
+}
@@ -1,40 +1,63 @@
-use std::cmp::max;
-use std::collections::{HashMap, HashSet};
+use std::collections::HashMap;
 use std::convert::Infallible;
-use std::env;
+use std::process;
 use std::ops::Add;
 use std::sync::{Arc, RwLock};
 use std::sync::atomic::AtomicUsize;
 
 use futures::future::{abortable, AbortHandle};
-use log::{error, info};
-use lru_time_cache::LruCache;
+use log::{error, info, warn};
 use serde::de::DeserializeOwned;
+use structopt::StructOpt;
 use tokio::time::{Duration, Instant};
 use warp::{Filter, Rejection};
 
 use model::{RoomHandle, User};
 use model::RoomDesc;
 
-use crate::model::{Message, MoveTimerUpdate, Player, TurnChangeUpdate};
-use crate::ws::{PlayerLeftUpdate, send_update};
-
+use crate::accounts::Account;
+use crate::config::{Config, Opts};
+use crate::matchmaking::Matchmaker;
+use crate::ratings::{GameResult, Ratings, ResultSender};
+use crate::sessions::Sessions;
+use crate::stats::Stats;
+use crate::tournament::Tournament;
+use crate::model::{MoveTimerUpdate, RoomStateUpdate, TurnChangeUpdate};
+use crate::storage::{FileStorage, save_snapshot, Snapshot, Storage};
+use crate::ws::{send_transient_update, send_update};
+
+mod accounts;
+mod board;
+mod bot;
+mod clock;
+mod config;
 mod handler;
+mod hex;
+mod matchmaking;
 mod ws;
 mod game;
 mod model;
+mod ratings;
+mod record;
+mod sessions;
+mod stats;
+mod tournament;
+mod storage;
 
-const HOST: &str = "127.0.0.1";
-const PORT: usize = 8000;
 const USER_TOKEN_HEADER: &str = "X-User-Token";
-const ROOM_TTL_SEC: u64 = 600;
-const PLAYER_TTL_SEC: u64 = 40;
+const EVENT_LOG_SIZE: usize = 200;
+const LAST_EVENT_ID_HEADER: &str = "Last-Event-ID";
 
 
 type Result<T> = std::result::Result<T, Rejection>;
 type RoomList = Arc<RwLock<HashMap<String, RoomHandle>>>;
 type RoomTimersList = Arc<RwLock<HashMap<String, AbortHandle>>>;
-type UserTokens = Arc<RwLock<LruCache<String, User>>>;
+type UserTokens = Arc<Sessions>;
+type AccountList = Arc<RwLock<HashMap<String, Account>>>;
+type RatingList = Arc<RwLock<Ratings>>;
+type StatsList = Arc<RwLock<Stats>>;
+type MatchQueue = Arc<RwLock<Matchmaker>>;
+type TournamentList = Arc<RwLock<HashMap<String, Tournament>>>;
 
 
 fn create_default_path<T>(path: &'static str, rooms: RoomList, users: UserTokens) -> impl Filter<Extract=(String, T, RoomList, Option<usize>, ), Error=Rejection> + Clone
@@ -47,26 +70,42 @@ where T: DeserializeOwned + Send {
         .and(with_userid(users))
 }
 
-fn start_timer(rooms: RoomList, room_timers: RoomTimersList, timeout: usize, room_id: String) {
+fn start_timer(rooms: RoomList, room_timers: RoomTimersList, room_id: String) {
     let mut interval = tokio::time::interval_at(Instant::now().add(Duration::from_secs(1)), Duration::from_secs(1));
     let local_room_id = room_id.clone();
+    let local_room_timers = room_timers.clone();
     if let Some(handle) = room_timers.clone().write().unwrap().remove(room_id.as_str()) {
         handle.abort()
     }
     let new_handle = async move {
-        let mut i = timeout;
         let local_rooms = rooms.clone();
         loop {
             interval.tick().await;
-            i -= 1;
             if let Some(mut r) = local_rooms.write().unwrap().get_mut(local_room_id.as_str()) {
                 let user_id = r.active_player;
-                send_update(r, &MoveTimerUpdate::new(i, user_id));
-                if i <= 0 {
-                    let next_player = RoomHandle::next_player(r.active_player, r.players.len());
-                    r.active_player = next_player.clone();
-                    send_update(r, &TurnChangeUpdate::new(next_player));
-                    i = timeout;
+                let player_id = r.players.get(user_id).map(|p| p.user_id).unwrap_or_default();
+                let expired = r.clock.tick(player_id);
+                send_transient_update(r, &MoveTimerUpdate::new(r.clock.time_left(player_id), user_id, r.clock.remaining.clone()));
+                if expired {
+                    let finished_before = r.standings.len();
+                    let (timeout, auto_move) = r.time_out();
+                    send_update(r, &timeout);
+                    handler::send_player_finished(r, finished_before);
+                    match auto_move {
+                        Some(update) => send_update(r, &update),
+                        None if r.game_finished => {
+                            let update = RoomStateUpdate::new(r);
+                            send_update(r, &update);
+                        }
+                        None => {
+                            let next_player = r.active_player;
+                            send_update(r, &TurnChangeUpdate::new(next_player));
+                        }
+                    }
+                    if r.game_finished {
+                        break;
+                    }
+                    bot::schedule_bot_move(rooms.clone(), local_room_timers.clone(), r);
                 }
             } else {
                 error!("Could not find room {}", local_room_id);
@@ -87,15 +126,96 @@ fn cancel_timer(room_timers: RoomTimersList, room_id: String) {
 
 #[tokio::main]
 async fn main() {
-    env::set_var("RUST_LOG", "info");
-    env_logger::init();
+    let opts = Opts::from_args();
+    let print_config = opts.print_config;
+    let config = match opts.into_config() {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("Invalid configuration: {}", e);
+            process::exit(1);
+        }
+    };
+    if print_config {
+        let printed = Config { token_secret: config.token_secret.as_ref().map(|_| "<hidden>".to_string()), ..config.as_ref().clone() };
+        print!("{}", toml::to_string(&printed).expect("Config is serializable"));
+        return;
+    }
+    env_logger::Builder::new().parse_filters(&config.log_level).init();
     let rooms = Arc::new(RwLock::new(HashMap::new()));
     let room_timers: RoomTimersList = Arc::new(RwLock::new(HashMap::new()));
     let users_count = Arc::new(AtomicUsize::new(0));
-    let time_to_live = ::std::time::Duration::from_secs(3600 * 24);
-    let users: UserTokens = Arc::new(RwLock::new(LruCache::<String, User>::with_expiry_duration(time_to_live)));
+    let time_to_live = ::std::time::Duration::from_secs(config.token_ttl_sec);
+    let secret = match &config.token_secret {
+        Some(secret) => secret.as_bytes().to_vec(),
+        None => {
+            warn!("No token secret configured, tokens will not survive a restart.");
+            Sessions::random_secret()
+        }
+    };
+    let users: UserTokens = Arc::new(Sessions::new(secret, time_to_live));
+    let accounts: AccountList = Arc::new(RwLock::new(HashMap::new()));
+    let ratings: RatingList = Arc::new(RwLock::new(Ratings::default()));
+    let stats: StatsList = Arc::new(RwLock::new(Stats::default()));
+    let queue: MatchQueue = Arc::new(RwLock::new(Matchmaker::default()));
+    let tournaments: TournamentList = Arc::new(RwLock::new(HashMap::new()));
+    let (results, mut results_receiver) = tokio::sync::mpsc::unbounded_channel::<GameResult>();
+    let storage: Arc<dyn Storage> = Arc::new(FileStorage::new(config.storage_file.clone()));
+    match storage.load() {
+        Ok(Some(mut snapshot)) => {
+            tournaments.write().unwrap().extend(snapshot.tournaments.drain(..).map(|t| (t.id.clone(), t)));
+            let in_progress = snapshot.restore(&rooms, &users, &accounts, &ratings, &stats, &users_count);
+            for room_id in in_progress.iter() {
+                start_timer(rooms.clone(), room_timers.clone(), room_id.clone());
+            }
+            bot::resume_bots(rooms.clone(), room_timers.clone(), &in_progress);
+            for room in rooms.write().unwrap().values_mut().filter(|r| !r.game_finished) {
+                room.results = Some(results.clone());
+            }
+        }
+        Ok(None) => info!("No saved state found in {}.", config.storage_file.display()),
+        Err(e) => error!("Could not load saved state from {}: {:?}", config.storage_file.display(), e)
+    }
+    let snapshot_period = Duration::from_secs(config.snapshot_interval_sec);
+    let mut snapshot_interval = tokio::time::interval_at(Instant::now().add(snapshot_period), snapshot_period);
+    let snapshot_storage = storage.clone();
+    let snapshot_rooms = rooms.clone();
+    let snapshot_users = users.clone();
+    let snapshot_users_count = users_count.clone();
+    let snapshot_accounts = accounts.clone();
+    let snapshot_ratings = ratings.clone();
+    let snapshot_stats = stats.clone();
+    let snapshot_tournaments = tournaments.clone();
+    tokio::spawn(async move {
+        loop {
+            snapshot_interval.tick().await;
+            let snapshot = Snapshot::capture(&snapshot_rooms, &snapshot_users, &snapshot_accounts, &snapshot_ratings, &snapshot_stats, &snapshot_users_count)
+                .with_tournaments(&snapshot_tournaments);
+            save_snapshot(snapshot_storage.as_ref(), &snapshot);
+        }
+    });
+    let results_ratings = ratings.clone();
+    let results_stats = stats.clone();
+    let results_tournaments = tournaments.clone();
+    let results_rooms = rooms.clone();
+    let results_sender = results.clone();
+    let results_config = config.clone();
+    tokio::spawn(async move {
+        while let Some(result) = results_receiver.recv().await {
+            info!("Rating the game in room {}: {:?}", result.room_id, result.places);
+            results_ratings.write().unwrap().apply(&result);
+            results_stats.write().unwrap().apply(&result);
+            let advanced: Vec<String> = results_tournaments.write().unwrap().values_mut()
+                .filter_map(|t| if t.record(&result.room_id, result.winner, &result.forfeited) { Some(t.id.clone()) } else { None })
+                .collect();
+            for id in advanced {
+                handler::seat_tournament_games(&id, &results_tournaments, &results_rooms, &results_sender, &results_config).await;
+            }
+        }
+    });
     let health_route = warp::path!("health").and_then(handler::health_handler);
-    let mut interval = tokio::time::interval_at(Instant::now().add(Duration::from_secs(ROOM_TTL_SEC)), Duration::from_secs(ROOM_TTL_SEC));
+    let room_ttl = Duration::from_secs(config.room_ttl_sec);
+    let player_ttl = Duration::from_secs(config.player_ttl_sec);
+    let mut interval = tokio::time::interval_at(Instant::now().add(room_ttl), room_ttl);
     let rooms_cloned = rooms.clone();
     let rooms_timers_cloned = room_timers.clone();
     tokio::spawn( async move {
@@ -105,42 +225,17 @@ async fn main() {
                 info!("Removing stale rooms.");
                 rs.retain(|_, room: &mut RoomHandle| {
                     let last_updated: Duration = std::time::Instant::now() - room.last_updated;
-                    room.players.len() != 0 || last_updated < Duration::from_secs(ROOM_TTL_SEC)
+                    let stale = room.players.is_empty() && last_updated >= room_ttl;
+                    if stale {
+                        room.abandon();
+                    }
+                    !stale
                 });
 
                 rooms_timers_cloned.clone().write().unwrap().retain(|k, _| { rs.contains_key(k) });
 
                 for (_, handler) in rs.iter_mut() {
-                    for p in handler.players.iter_mut() {
-                        if p.sender.send(Ok(Message::event("test".to_string()))).is_ok() {
-                            p.last_active = std::time::Instant::now();
-                        }
-                    }
-                    handler.players.retain(|p: &Player| {
-                        std::time::Instant::now() - p.last_active < Duration::from_secs(PLAYER_TTL_SEC)
-                    });
-                    let players = &handler.players;
-                    let mut removed_players = Vec::new();
-                    let mut removed_colors = HashSet::new();
-                    let room_id = handler.room_id.clone();
-                    let game_started = handler.game_started;
-                    handler.active_player %= max(handler.players.len(), 1);
-                    if let Some(gs) = handler.game_state.as_mut() {
-                        gs.players_colors.retain(|id, c| {
-                            let valid = players.iter().any(|p|{ p.user_id == *id});
-                            if !valid {
-                                removed_players.push((*id, *c));
-                                removed_colors.insert(*c);
-                            }
-                            valid
-                        });
-                        if !game_started {
-                           gs.cones.retain(|(_, _), color| { !removed_colors.contains(color) })
-                        }
-                    }
-                    for (user_id, player_color) in removed_players.iter() {
-                        send_update(handler, &PlayerLeftUpdate::new(*user_id, room_id.clone(), handler.active_player, !game_started, *player_color));
-                    }
+                    ws::remove_stale_players(handler, player_ttl);
                 }
             } else {
                 info!("Could not acquire lock for removing stale rooms.");
@@ -154,6 +249,46 @@ async fn main() {
         .and(with_userid(users.clone()))
         .and(warp::body::json())
         .and_then(handler::validate_path);
+    let legal_moves = warp::path("moves")
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(warp::query())
+        .and(with_rooms(rooms.clone()))
+        .and(with_userid(users.clone()))
+        .and_then(handler::get_legal_moves);
+    let add_bot = warp::path("bot")
+        .and(warp::post())
+        .and(warp::path::param())
+        .and(warp::body::json())
+        .and(with_rooms(rooms.clone()))
+        .and(with_userid(users.clone()))
+        .and(with_users_counter(users_count.clone()))
+        .and_then(handler::add_bot_handler);
+    let history = warp::path("history")
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(with_rooms(rooms.clone()))
+        .and_then(handler::get_history);
+    let replay = warp::path("replay")
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(warp::query())
+        .and(with_rooms(rooms.clone()))
+        .and_then(handler::replay_handler);
+    let record = warp::path("record")
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(with_rooms(rooms.clone()))
+        .and_then(handler::get_record);
+    let import_record = warp::path("import")
+        .and(warp::post())
+        .and(with_userid(users.clone()))
+        .and(warp::header::optional(USER_TOKEN_HEADER))
+        .and(warp::body::content_length_limit(1024 * 1024))
+        .and(warp::body::bytes())
+        .and(with_rooms(rooms.clone()))
+        .and(with_config(config.clone()))
+        .and_then(handler::import_record_handler);
     let get_players = warp::path("players")
         .and(warp::get())
         .and(warp::query())
@@ -171,11 +306,138 @@ async fn main() {
         .and(with_users(users.clone()))
         .and(with_users_counter(users_count.clone()))
         .and_then(handler::add_user_handle);
-    let refresh_token = warp::path("refresh")
+    let signup = warp::path("signup")
+        .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and(with_accounts(accounts.clone()))
+        .and(with_users(users.clone()))
+        .and(with_users_counter(users_count.clone()))
+        .and_then(handler::signup_handler);
+    let login = warp::path("login")
         .and(warp::post())
+        .and(warp::body::content_length_limit(1024 * 32))
+        .and(warp::body::json())
+        .and(with_accounts(accounts.clone()))
+        .and(with_users(users.clone()))
+        .and_then(handler::login_handler);
+    let profile = warp::path("profile");
+    let profile_routes = profile
+        .and(warp::get())
         .and(with_user(users.clone()))
+        .and(with_accounts(accounts.clone()))
+        .and_then(handler::get_profile)
+        .or(profile
+            .and(warp::post())
+            .and(with_user(users.clone()))
+            .and(warp::body::content_length_limit(1024 * 32))
+            .and(warp::body::json())
+            .and(with_accounts(accounts.clone()))
+            .and_then(handler::update_profile));
+    let refresh_token = warp::path("refresh")
+        .and(warp::post())
+        .and(warp::header::optional(USER_TOKEN_HEADER))
         .and(with_users(users.clone()))
+        .and(with_accounts(accounts.clone()))
         .and_then(handler::refresh_token_handle);
+    let rating = warp::path("rating");
+    let rating_routes = rating
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(warp::path("history"))
+        .and(with_ratings(ratings.clone()))
+        .and_then(handler::get_rating_history)
+        .or(rating
+            .and(warp::get())
+            .and(warp::path::param())
+            .and(with_ratings(ratings.clone()))
+            .and_then(handler::get_rating));
+    let stats_route = warp::path("stats")
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(with_stats(stats.clone()))
+        .and(with_ratings(ratings.clone()))
+        .and_then(handler::get_stats);
+    let leaderboard = warp::path("leaderboard")
+        .and(warp::get())
+        .and(warp::query())
+        .and(with_ratings(ratings.clone()))
+        .and(with_stats(stats.clone()))
+        .and_then(handler::get_leaderboard);
+    let queue_path = warp::path("queue");
+    let queue_routes = queue_path
+        .and(warp::post())
+        .and(with_user(users.clone()))
+        .and(warp::body::json())
+        .and(with_queue(queue.clone()))
+        .and(with_rooms(rooms.clone()))
+        .and(with_ratings(ratings.clone()))
+        .and(with_results(results.clone()))
+        .and(with_config(config.clone()))
+        .and_then(handler::join_queue)
+        .or(queue_path
+            .and(warp::delete())
+            .and(with_userid(users.clone()))
+            .and(with_queue(queue.clone()))
+            .and_then(handler::leave_queue))
+        .or(queue_path
+            .and(warp::get())
+            .and(with_user_from_token(users.clone()))
+            .and(with_queue(queue.clone()))
+            .and_then(handler::queue_sse_handler));
+    let tournament = warp::path("tournament");
+    let tournament_routes = tournament
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(warp::path("standings"))
+        .and(with_tournaments(tournaments.clone()))
+        .and_then(handler::get_tournament_standings)
+        .or(tournament
+            .and(warp::get())
+            .and(warp::path::param())
+            .and(warp::path("bracket"))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(handler::get_bracket))
+        .or(tournament
+            .and(warp::post())
+            .and(warp::path::param())
+            .and(warp::path("register"))
+            .and(with_user(users.clone()))
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(handler::register_tournament))
+        .or(tournament
+            .and(warp::post())
+            .and(warp::path::param())
+            .and(warp::path("start"))
+            .and(with_userid(users.clone()))
+            .and(with_tournaments(tournaments.clone()))
+            .and(with_ratings(ratings.clone()))
+            .and(with_rooms(rooms.clone()))
+            .and(with_results(results.clone()))
+            .and(with_config(config.clone()))
+            .and_then(handler::start_tournament))
+        .or(tournament
+            .and(warp::get())
+            .and(warp::path::param())
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(handler::get_tournament))
+        .or(tournament
+            .and(warp::post())
+            .and(warp::path::end())
+            .and(with_user(users.clone()))
+            .and(warp::body::json())
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(handler::create_tournament))
+        .or(tournament
+            .and(warp::get())
+            .and(warp::path::end())
+            .and(with_tournaments(tournaments.clone()))
+            .and_then(handler::get_tournaments));
+    let logout = warp::path("logout")
+        .and(warp::post())
+        .and(warp::header::optional(USER_TOKEN_HEADER))
+        .and(with_users(users.clone()))
+        .and_then(handler::logout_handler);
 
     let room = warp::path("room");
     let room_moves = "move";
@@ -184,8 +446,11 @@ async fn main() {
     let room_handle_routes = room
         .and(warp::post())
         .and(with_userid(users.clone()))
+        .and(warp::header::optional(USER_TOKEN_HEADER))
         .and(warp::body::json())
         .and(with_rooms(rooms.clone()))
+        .and(with_config(config.clone()))
+        .and(with_results(results.clone()))
         .and_then(handler::create_room_handler)
         .or(room
             .and(warp::get())
@@ -220,12 +485,35 @@ async fn main() {
         .and(warp::path::param())
         .and(with_user_from_token(users.clone()))
         .and(with_rooms(rooms.clone()))
-        .and_then(|room_id: String, user: Option<User>, rooms: RoomList| async {
-            handler::sse_handler(room_id, user, rooms).map(|stream| { warp::sse::reply(warp::sse::keep_alive().stream(stream)) })
+        .and(warp::header::optional(LAST_EVENT_ID_HEADER))
+        .and_then(|room_id: String, user: Option<User>, rooms: RoomList, last_event_id: Option<u64>| async move {
+            handler::sse_handler(room_id, user, rooms, last_event_id).map(|stream| { warp::sse::reply(warp::sse::keep_alive().stream(stream)) })
         });
 
-    let cors = warp::cors()
-        .allow_any_origin()
+    let watch_route = warp::path("watch")
+        .and(warp::get())
+        .and(warp::path::param())
+        .and(with_user_from_token(users.clone()))
+        .and(with_rooms(rooms.clone()))
+        .and(warp::header::optional(LAST_EVENT_ID_HEADER))
+        .and_then(|room_id: String, user: Option<User>, rooms: RoomList, last_event_id: Option<u64>| async move {
+            handler::spectator_sse_handler(room_id, user, rooms, last_event_id).map(|stream| { warp::sse::reply(warp::sse::keep_alive().stream(stream)) })
+        });
+
+    let socket_route = warp::path("ws")
+        .and(warp::path::param())
+        .and(with_user_from_token(users.clone()))
+        .and(with_rooms(rooms.clone()))
+        .and(with_rooms_timers(room_timers.clone()))
+        .and(warp::ws())
+        .and_then(handler::socket_handler);
+
+    let cors = if config.cors_origins.is_empty() {
+        warp::cors().allow_any_origin()
+    } else {
+        warp::cors().allow_origins(config.cors_origins.iter().map(String::as_str))
+    };
+    let cors = cors
         .allow_methods(vec!["POST", "GET", "DELETE", "OPTIONS"])
         .allow_headers(vec!["content-type",
                             USER_TOKEN_HEADER,
@@ -235,28 +523,63 @@ async fn main() {
                             "Sec-Fetch-Site"
         ]);
 
+    let allow_any_origin = config.cors_origins.is_empty();
     let routes = health_route
         .or(room_handle_routes)
         .or(room_messages_routes)
         .or(add_user)
+        .or(signup)
+        .or(login)
+        .or(profile_routes)
         .or(get_players)
         .or(game_state)
         .or(refresh_token)
+        .or(logout)
+        .or(rating_routes)
+        .or(stats_route)
+        .or(leaderboard)
+        .or(queue_routes)
+        .or(tournament_routes)
         .or(validate_path)
+        .or(legal_moves)
+        .or(add_bot)
+        .or(history)
+        .or(replay)
+        .or(record)
+        .or(import_record)
         .or(room_updates_routes)
         .or(room_chat_routes)
         .or(sse_route)
+        .or(watch_route)
+        .or(socket_route)
         // .or(publish)
         .with(cors)
         .recover(handler::handle_rejection)
-        .map(|reply| {
-            warp::reply::with_header(reply, "Access-Control-Allow-Origin", "*")
+        .map(move |reply| -> Box<dyn warp::Reply> {
+            // Error replies bypass the CORS filter, with a list of origins they stay without the header.
+            if allow_any_origin {
+                Box::new(warp::reply::with_header(reply, "Access-Control-Allow-Origin", "*"))
+            } else {
+                Box::new(reply)
+            }
         })
         .with(warp::log::log("tests"));
 
 
 
-    warp::serve(routes).run(([127, 0, 0, 1], PORT as u16)).await;
+    let (_, server) = warp::serve(routes).bind_with_graceful_shutdown(config.bind_address(), shutdown_signal());
+    server.await;
+    info!("Saving state before shutdown.");
+    save_snapshot(storage.as_ref(), &Snapshot::capture(&rooms, &users, &accounts, &ratings, &stats, &users_count).with_tournaments(&tournaments));
+}
+
+async fn shutdown_signal() {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())
+        .expect("Could not install SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
+    }
 }
 
 
@@ -264,6 +587,10 @@ fn with_rooms(rooms: RoomList) -> impl Filter<Extract=(RoomList, ), Error=Infall
     warp::any().map(move || rooms.clone())
 }
 
+fn with_config(config: Arc<Config>) -> impl Filter<Extract=(Arc<Config>, ), Error=Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
 fn with_rooms_timers(rooms_timers: RoomTimersList) -> impl Filter<Extract=(RoomTimersList, ), Error=Infallible> + Clone {
     warp::any().map(move || rooms_timers.clone())
 }
@@ -272,28 +599,48 @@ fn with_users(users: UserTokens) -> impl Filter<Extract=(UserTokens, ), Error=In
     warp::any().map(move || users.clone())
 }
 
+fn with_accounts(accounts: AccountList) -> impl Filter<Extract=(AccountList, ), Error=Infallible> + Clone {
+    warp::any().map(move || accounts.clone())
+}
+
+fn with_ratings(ratings: RatingList) -> impl Filter<Extract=(RatingList, ), Error=Infallible> + Clone {
+    warp::any().map(move || ratings.clone())
+}
+
+fn with_stats(stats: StatsList) -> impl Filter<Extract=(StatsList, ), Error=Infallible> + Clone {
+    warp::any().map(move || stats.clone())
+}
+
+fn with_tournaments(tournaments: TournamentList) -> impl Filter<Extract=(TournamentList, ), Error=Infallible> + Clone {
+    warp::any().map(move || tournaments.clone())
+}
+
+fn with_queue(queue: MatchQueue) -> impl Filter<Extract=(MatchQueue, ), Error=Infallible> + Clone {
+    warp::any().map(move || queue.clone())
+}
+
+fn with_results(results: ResultSender) -> impl Filter<Extract=(ResultSender, ), Error=Infallible> + Clone {
+    warp::any().map(move || results.clone())
+}
+
 fn with_users_counter(userscounts: Arc<AtomicUsize>) -> impl Filter<Extract=(Arc<AtomicUsize>, ), Error=Infallible> + Clone {
     warp::any().map(move || userscounts.clone())
 }
 
 fn with_userid(users: UserTokens) -> impl Filter<Extract=(Option<usize>, ), Error=Rejection> + Clone {
     warp::header::optional(USER_TOKEN_HEADER).map(move |token: Option<String>| {
-        token.map(|t| {
-            users.write().unwrap().get(&t).map(|user| { user.user_id })
-        }).flatten()
+        token.and_then(|t| users.verify(&t)).map(|claims| claims.uid)
     })
 }
 
 fn with_user_from_token(users: UserTokens) -> impl Filter<Extract=(Option<User>, ), Error=Rejection> + Clone {
     warp::path::param().map(move |token: String| {
-        users.write().unwrap().get(&token).map(|user| { user }).cloned()
+        users.verify(&token).map(|claims| { claims.user() })
     })
 }
 
 fn with_user(users: UserTokens) -> impl Filter<Extract=(Option<User>, ), Error=Rejection> + Clone {
     warp::header::optional(USER_TOKEN_HEADER).map(move |token: Option<String>| {
-        token.map(|t| {
-            users.write().unwrap().get(&t).cloned()
-        }).flatten()
+        token.and_then(|t| users.verify(&t)).map(|claims| claims.user())
     })
 }
\ No newline at end of file
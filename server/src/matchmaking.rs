@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+use std::fmt::Debug;
+
+use log::error;
+use serde::Serialize;
+use tokio::sync::mpsc;
+
+use crate::game::{BLUE, GREEN, ORANGE, PURPLE, RED, YELLOW};
+use crate::model::{Message, User};
+
+pub const MATCH_SIZES: [usize; 4] = [2, 3, 4, 6];
+
+type QueueSender = mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>;
+
+/// Colors of a quick game, every player starts opposite to an empty triangle or to another player.
+pub fn match_colors(players: usize) -> &'static [usize] {
+    match players {
+        2 => &[PURPLE, YELLOW],
+        3 => &[PURPLE, ORANGE, RED],
+        4 => &[GREEN, ORANGE, RED, BLUE],
+        _ => &[PURPLE, GREEN, ORANGE, YELLOW, RED, BLUE]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct QueueEntry {
+    pub user: User,
+    pub players: usize,
+    pub rating: f64,
+    /// Ratings of the opponents the user wants to play with.
+    pub min_rating: Option<f64>,
+    pub max_rating: Option<f64>,
+}
+
+impl QueueEntry {
+    fn accepts(&self, rating: f64) -> bool {
+        self.min_rating.map_or(true, |min| rating >= min) && self.max_rating.map_or(true, |max| rating <= max)
+    }
+
+    fn matches(&self, other: &QueueEntry) -> bool {
+        self.players == other.players && self.accepts(other.rating) && other.accepts(self.rating)
+    }
+}
+
+/// Users waiting for a quick game, in the order they came, and their event streams.
+#[derive(Debug, Default)]
+pub struct Matchmaker {
+    queue: Vec<QueueEntry>,
+    streams: HashMap<usize, QueueSender>,
+    // Updates that could not be delivered, sent when the user connects.
+    pending: HashMap<usize, String>,
+}
+
+impl Matchmaker {
+    /// Puts the user in the queue, replacing the previous entry. When enough players accept each other
+    /// they leave the queue and are returned, the ones waiting longest come first.
+    pub fn join(&mut self, entry: QueueEntry) -> Option<Vec<QueueEntry>> {
+        self.leave(entry.user.user_id);
+        self.pending.remove(&entry.user.user_id);
+        let mut group = vec![entry.clone()];
+        for candidate in self.queue.iter() {
+            if group.len() == entry.players {
+                break;
+            }
+            if group.iter().all(|g| g.matches(candidate)) {
+                group.push(candidate.clone());
+            }
+        }
+        if group.len() == entry.players {
+            self.queue.retain(|e| !group.iter().any(|g| g.user.user_id == e.user.user_id));
+            group.rotate_left(1);
+            Some(group)
+        } else {
+            self.queue.push(entry);
+            None
+        }
+    }
+
+    pub fn leave(&mut self, user_id: usize) -> bool {
+        let before = self.queue.len();
+        self.queue.retain(|e| e.user.user_id != user_id);
+        self.queue.len() != before
+    }
+
+    /// Place in the queue, starting at 1.
+    pub fn position(&self, user_id: usize) -> Option<usize> {
+        self.queue.iter().position(|e| e.user.user_id == user_id).map(|p| p + 1)
+    }
+
+    pub fn connect(&mut self, user_id: usize, sender: QueueSender) {
+        if let Some(text) = self.pending.remove(&user_id) {
+            if sender.send(Ok(Message::Text(text))).is_err() {
+                error!("Could not send a pending update to user {}", user_id);
+            }
+        }
+        self.streams.insert(user_id, sender);
+    }
+
+    pub fn notify(&mut self, user_id: usize, update: &(impl Serialize + Debug)) {
+        let text = match serde_json::to_string(update) {
+            Ok(text) => text,
+            Err(e) => {
+                error!("Could not serialize {:?}: {}", update, e);
+                return;
+            }
+        };
+        let delivered = self.streams.get(&user_id)
+            .is_some_and(|s| s.send(Ok(Message::Text(text.clone()))).is_ok());
+        if !delivered {
+            self.streams.remove(&user_id);
+            self.pending.insert(user_id, text);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(user_id: usize, players: usize, rating: f64, range: Option<(f64, f64)>) -> QueueEntry {
+        QueueEntry {
+            user: User { user_id, user_name: format!("user{}", user_id), preferred_color: None },
+            players,
+            rating,
+            min_rating: range.map(|r| r.0),
+            max_rating: range.map(|r| r.1),
+        }
+    }
+
+    fn ids(group: Vec<QueueEntry>) -> Vec<usize> {
+        group.iter().map(|e| e.user.user_id).collect()
+    }
+
+    #[test]
+    fn test_players_are_matched_by_count() {
+        let mut m = Matchmaker::default();
+        assert!(m.join(entry(1, 3, 1500.0, None)).is_none());
+        assert!(m.join(entry(2, 2, 1500.0, None)).is_none());
+        assert!(m.join(entry(3, 3, 1500.0, None)).is_none());
+        assert_eq!(Some(3), m.position(3));
+        assert_eq!(vec![1, 3, 4], ids(m.join(entry(4, 3, 1500.0, None)).unwrap()));
+        assert_eq!(Some(1), m.position(2));
+        assert_eq!(None, m.position(1));
+    }
+
+    #[test]
+    fn test_rating_ranges_are_respected_both_ways() {
+        let mut m = Matchmaker::default();
+        assert!(m.join(entry(1, 2, 1800.0, Some((1700.0, 2000.0)))).is_none());
+        assert!(m.join(entry(2, 2, 1500.0, None)).is_none());
+        assert!(m.join(entry(3, 2, 1750.0, Some((1000.0, 1600.0)))).is_some());
+        assert_eq!(None, m.position(2));
+        assert_eq!(Some(1), m.position(1));
+        assert!(m.leave(1));
+        assert!(!m.leave(1));
+    }
+
+    #[test]
+    fn test_updates_wait_for_the_stream() {
+        let mut m = Matchmaker::default();
+        m.notify(1, &"match");
+        let (sender, mut receiver) = mpsc::unbounded_channel();
+        m.connect(1, sender);
+        match receiver.try_recv() {
+            Ok(Ok(Message::Text(text))) => assert_eq!("\"match\"", text),
+            other => panic!("Unexpected message {:?}", other)
+        }
+    }
+}
@@ -1,9 +1,19 @@
 use std::cmp::max;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::time::Instant;
-use crate::game::GameState;
+use crate::accounts::Account;
+use crate::board::Board;
+use crate::bot::{Bot, Difficulty};
+use crate::config::Config;
+use crate::clock::{GameClock, TimeControl, TimeoutAction, TimeoutPolicy};
+use crate::game::{GameHistory, GameState, NEUTRAL, Rules};
+use crate::ratings::{GameResult, Rating, ResultSender};
+use crate::stats::PlayerStats;
+use crate::tournament::{Tournament, TournamentFormat, TournamentStatus};
 use tokio::sync::mpsc;
 use serde::{Serialize, Deserialize};
 use log::{error};
+use crate::EVENT_LOG_SIZE;
 use crate::model::Message::{Event};
 
 #[derive(Debug)]
@@ -19,6 +29,52 @@ pub struct RoomHandle {
     pub active_player: usize,
     pub game_state: Option<GameState>,
     pub players: Vec<Player>,
+    pub spectators: Vec<Spectator>,
+    pub history: GameHistory,
+    pub events: EventLog,
+    pub clock: GameClock,
+    pub forfeited: Vec<usize>,
+    /// Players who dropped out of a running game while somebody else stayed, until they come back.
+    pub walked_out: Vec<usize>,
+    /// Where the result goes when the game is over, unrated rooms have none.
+    pub results: Option<ResultSender>,
+    /// Keep playing after the first player is done, until every place is taken.
+    pub play_to_end: bool,
+    /// Players with all cones in place, in the order they finished.
+    pub standings: Vec<usize>,
+    /// Only the players given a color beforehand can take a seat, the others can watch.
+    pub private: bool,
+    /// Seats kept for the players matched or paired into the room, user id to color.
+    pub reserved: HashMap<usize, usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct EventLog {
+    pub last_id: u64,
+    events: VecDeque<(u64, String)>,
+}
+
+impl EventLog {
+    pub fn with_last_id(last_id: u64) -> EventLog {
+        EventLog { last_id, events: VecDeque::new() }
+    }
+
+    pub fn push(&mut self, event: String) -> u64 {
+        self.last_id += 1;
+        self.events.push_back((self.last_id, event));
+        while self.events.len() > EVENT_LOG_SIZE {
+            self.events.pop_front();
+        }
+        self.last_id
+    }
+
+    pub fn first_id(&self) -> Option<u64> {
+        self.events.front().map(|(id, _)| *id)
+    }
+
+    pub fn since(&self, last_id: u64) -> impl Iterator<Item=&(u64, String)> {
+        self.events.iter().filter(move |(id, _)| *id > last_id)
+    }
 }
 
 #[derive(Deserialize)]
@@ -26,6 +82,111 @@ pub struct AddUserRequest {
     pub name: String
 }
 
+#[derive(Deserialize)]
+pub struct SignupRequest {
+    pub login: String,
+    pub password: String,
+    pub display_name: Option<String>,
+    pub preferred_color: Option<usize>
+}
+
+#[derive(Deserialize)]
+pub struct LoginRequest {
+    pub login: String,
+    pub password: String
+}
+
+#[derive(Deserialize)]
+pub struct UpdateProfileRequest {
+    pub display_name: Option<String>,
+    pub preferred_color: Option<usize>
+}
+
+#[derive(Serialize)]
+pub struct ProfileResponse {
+    pub user_id: usize,
+    pub login: String,
+    pub display_name: String,
+    pub preferred_color: Option<usize>
+}
+
+impl ProfileResponse {
+    pub fn from_account(account: &Account) -> ProfileResponse {
+        ProfileResponse {
+            user_id: account.user_id,
+            login: account.login.clone(),
+            display_name: account.display_name.clone(),
+            preferred_color: account.preferred_color,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RatingResponse {
+    pub user_id: usize,
+    pub rating: i64,
+    pub games: usize,
+}
+
+impl RatingResponse {
+    pub fn from_rating(rating: &Rating) -> RatingResponse {
+        RatingResponse {
+            user_id: rating.user_id,
+            rating: rating.rating.round() as i64,
+            games: rating.games,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct StatsResponse {
+    pub user_id: usize,
+    pub name: Option<String>,
+    pub rating: i64,
+    pub games: usize,
+    pub wins: usize,
+    pub places: BTreeMap<usize, usize>,
+    pub finished_games: usize,
+    pub avg_moves_to_finish: Option<f64>,
+    pub avg_move_time_ms: Option<u64>,
+    pub longest_chain: usize,
+}
+
+impl StatsResponse {
+    pub fn new(stats: &PlayerStats, rating: &Rating) -> StatsResponse {
+        StatsResponse {
+            user_id: stats.user_id,
+            name: stats.name.clone(),
+            rating: rating.rating.round() as i64,
+            games: stats.games,
+            wins: stats.wins,
+            places: stats.places.clone(),
+            finished_games: stats.finished_games,
+            avg_moves_to_finish: stats.avg_moves_to_finish(),
+            avg_move_time_ms: stats.avg_move_time_ms(),
+            longest_chain: stats.longest_chain,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct LeaderboardEntry {
+    pub rank: usize,
+    pub user_id: usize,
+    pub name: Option<String>,
+    pub rating: i64,
+    pub games: usize,
+    pub wins: usize,
+}
+
+#[derive(Serialize)]
+pub struct LeaderboardResponse {
+    pub page: usize,
+    pub per_page: usize,
+    pub total: usize,
+    pub entries: Vec<LeaderboardEntry>,
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct RoomUpdate {
     name: String,
@@ -62,15 +223,17 @@ pub struct MoveTimerUpdate {
     name: String,
     pub timer_value: usize,
     pub user_id: usize,
+    pub clocks: HashMap<usize, usize>,
 }
 
 
 impl MoveTimerUpdate {
-    pub fn new(timer_value: usize, user_id: usize) -> MoveTimerUpdate {
+    pub fn new(timer_value: usize, user_id: usize, clocks: HashMap<usize, usize>) -> MoveTimerUpdate {
         MoveTimerUpdate {
             name: "move_timer".to_string(),
             timer_value,
             user_id,
+            clocks,
         }
     }
 }
@@ -90,6 +253,44 @@ impl TurnChangeUpdate {
     }
 }
 
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerTimeoutUpdate {
+    name: String,
+    pub user_id: usize,
+    pub consecutive_timeouts: usize,
+    pub action: TimeoutAction,
+    pub forfeited: bool,
+}
+
+impl PlayerTimeoutUpdate {
+    pub fn new(user_id: usize, consecutive_timeouts: usize, action: TimeoutAction, forfeited: bool) -> PlayerTimeoutUpdate {
+        PlayerTimeoutUpdate {
+            name: "player_timeout".to_string(),
+            user_id,
+            consecutive_timeouts,
+            action,
+            forfeited,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PlayerFinishedUpdate {
+    name: String,
+    pub user_id: usize,
+    pub place: usize,
+}
+
+impl PlayerFinishedUpdate {
+    pub fn new(user_id: usize, place: usize) -> PlayerFinishedUpdate {
+        PlayerFinishedUpdate {
+            name: "player_finished".to_string(),
+            user_id,
+            place,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize)]
 pub struct GameColorsUpdate<'a> {
     name: &'a str,
@@ -121,22 +322,203 @@ impl RoomHandle {
     pub fn next_player(player: usize, total_players: usize) -> usize {
         return (player + 1) % max(total_players, 1);
     }
-    pub fn make_a_move(&mut self, path: Vec<(i32, i32)>, user_id: usize) -> std::result::Result<RoomUpdate, usize> {
+    // Like next_player, but skips the players who forfeited or finished, and the ones waiting for their partners.
+    pub fn next_active_player(&self) -> usize {
+        let total = max(self.players.len(), 1);
+        (1..=total)
+            .map(|i| (self.active_player + i) % total)
+            .find(|i| self.players.get(*i).is_some_and(|p| self.is_playing(p.user_id) && !self.is_home(p.user_id)))
+            .unwrap_or_else(|| RoomHandle::next_player(self.active_player, self.players.len()))
+    }
+
+    fn is_playing(&self, user_id: usize) -> bool {
+        !self.forfeited.contains(&user_id) && !self.standings.contains(&user_id)
+    }
+
+    fn players_left(&self) -> Vec<usize> {
+        self.players.iter().map(|p| p.user_id).filter(|id| self.is_playing(*id)).collect()
+    }
+
+    fn is_home(&self, user_id: usize) -> bool {
+        self.game_state.as_ref().is_some_and(|gs| gs.is_all_cones_in_place(&user_id).unwrap_or(false))
+    }
+
+    pub fn team_of(&self, user_id: usize) -> Option<usize> {
+        self.game_state.as_ref().and_then(|gs| gs.team_of(&user_id))
+    }
+
+    fn are_partners(&self, user_id: usize, other: usize) -> bool {
+        self.game_state.as_ref().map_or(user_id == other, |gs| gs.are_partners(&user_id, &other))
+    }
+
+    // The player and the partners who did not forfeit.
+    fn team(&self, user_id: usize) -> Vec<usize> {
+        self.players.iter().map(|p| p.user_id)
+            .filter(|id| !self.forfeited.contains(id) && self.are_partners(user_id, *id))
+            .collect()
+    }
+
+    // Number of teams still playing, a player without a team is a team of one.
+    fn sides_left(&self) -> usize {
+        let left = self.players_left();
+        left.iter().enumerate()
+            .filter(|(i, id)| !left[..*i].iter().any(|other| self.are_partners(*other, **id)))
+            .count()
+    }
+
+    /// Places of the players, starting at 1, partners share the place of their team.
+    /// While the game goes on only the finished players are placed.
+    /// At the end the players still on the board share the next place, those who forfeited come last,
+    /// the ones who dropped out earlier after the others.
+    pub fn places(&self) -> Vec<(usize, usize)> {
+        let mut places: Vec<(usize, usize)> = Vec::new();
+        for (i, id) in self.standings.iter().enumerate() {
+            let place = match places.last() {
+                Some((previous, place)) if self.are_partners(*previous, *id) => *place,
+                _ => i + 1
+            };
+            places.push((*id, place));
+        }
+        if self.game_finished {
+            let left = self.players_left();
+            let mut place = places.len() + 1;
+            places.extend(left.iter().map(|id| (*id, place)));
+            if !left.is_empty() {
+                place += 1;
+            }
+            for (i, id) in self.forfeited.iter().rev().enumerate() {
+                places.push((*id, place + i));
+            }
+        }
+        places
+    }
+
+    /// Applies the timeout policy to the active player, who ran out of time.
+    /// Returns the move made on behalf of the player, if the policy asks for one.
+    pub fn time_out(&mut self) -> (PlayerTimeoutUpdate, Option<RoomUpdate>) {
+        let user_id = self.players.get(self.active_player).map(|p| p.user_id).unwrap_or_default();
+        let timeouts = self.clock.timed_out(user_id);
+        let action = self.clock.policy.action;
+        if self.clock.should_forfeit(user_id) {
+            self.forfeit(user_id);
+            return (PlayerTimeoutUpdate::new(user_id, timeouts, action, true), None);
+        }
+        if action == TimeoutAction::AutoMove {
+            let seed = self.history.moves.len() as u64;
+            let path = self.game_state.as_ref()
+                .and_then(|gs| Bot::new(Difficulty::Medium, seed).choose_move(gs, user_id));
+            if let Some(update) = path.and_then(|p| self.make_a_move(p, user_id, false).ok()) {
+                // A move made by the server does not count as the player being back.
+                self.clock.timeouts.insert(user_id, timeouts);
+                return (PlayerTimeoutUpdate::new(user_id, timeouts, action, false), Some(update));
+            }
+        }
+        self.active_player = self.next_active_player();
+        self.clock.turn_passed();
+        (PlayerTimeoutUpdate::new(user_id, timeouts, TimeoutAction::Skip, false), None)
+    }
+
+    fn forfeit(&mut self, user_id: usize) {
+        self.forfeited.push(user_id);
+        if let Some(gs) = self.game_state.as_mut() {
+            gs.cones.retain(|_, id| *id != user_id);
+        }
+        self.history.record_forfeit(user_id);
+        // Partners already home do not wait for the player anymore.
+        if let Some(partner) = self.team(user_id).into_iter().find(|id| self.is_home(*id)) {
+            self.player_finished(partner);
+        }
+        if !self.game_finished {
+            if self.sides_left() <= 1 {
+                self.finish();
+            } else {
+                self.active_player = self.next_active_player();
+            }
+        }
+        self.clock.turn_passed();
+    }
+
+    fn player_finished(&mut self, user_id: usize) {
+        // A team finishes once the cones of all the partners are home.
+        let team = self.team(user_id);
+        if team.iter().any(|id| *id != user_id && !self.is_home(*id)) {
+            return;
+        }
+        self.standings.extend(team);
+        if !self.play_to_end || self.sides_left() <= 1 {
+            self.finish();
+        }
+    }
+
+    fn finish(&mut self) {
+        // The last team standing takes the next place.
+        if self.sides_left() == 1 {
+            let left = self.players_left();
+            self.standings.extend(left);
+        }
+        self.winner = self.standings.first().cloned();
+        self.game_finished = true;
+        if let Some(results) = self.results.take() {
+            if results.send(GameResult::from_room(self)).is_err() {
+                error!("Could not send the result of room {}", self.room_id);
+            }
+        }
+    }
+
+    /// Ends a game that is dropped before it is over, without a winner, so the result still goes out
+    /// and a tournament waiting for it can go on.
+    pub fn abandon(&mut self) {
+        if !self.game_finished && self.results.is_some() {
+            self.finish();
+        }
+    }
+
+    // With the spoiler rule a move can also fill up the destination triangle of somebody else.
+    fn finish_spoiled(&mut self) {
+        let spoiled: Vec<usize> = match self.game_state.as_ref() {
+            Some(gs) if gs.rules.spoiler && !self.game_finished => self.players_left().into_iter()
+                .filter(|id| gs.is_all_cones_in_place(id).unwrap_or(false))
+                .collect(),
+            _ => return
+        };
+        for id in spoiled {
+            if !self.game_finished && self.is_playing(id) {
+                self.player_finished(id);
+            }
+        }
+        if self.players.get(self.active_player).is_some_and(|p| !self.is_playing(p.user_id)) {
+            self.active_player = self.next_active_player();
+        }
+    }
+
+    pub fn make_a_move(&mut self, path: Vec<(i32, i32)>, user_id: usize, calculate_path: bool) -> std::result::Result<RoomUpdate, usize> {
+        let next = self.next_active_player();
         if let Some(gs) = self.game_state.as_mut() {
-            let next = RoomHandle::next_player(self.active_player, self.players.len());
             let p = (path[0].0 as usize, path[0].1 as usize);
             if let Some(id) = gs.cones.get(&p) {
                 if *id == user_id {
-                    let update = gs.update_cones(&path, &user_id)
-                        .map(|(path, game_finished)| {
-                            self.active_player = next;
-                            if game_finished {
-                                self.winner = Some(user_id);
-                                self.game_finished = true;
-                            }
-                            RoomUpdate::new_with_finished(user_id, path, next.clone(), game_finished)
-                        });
-                    return update;
+                    let path = if calculate_path {
+                        if path.len() != 2 {
+                            error!("Expected only start and end points to calculate a path, got: {:?}", path);
+                            return Err(0);
+                        }
+                        gs.find_path(path[0], path[1])?
+                    } else {
+                        path
+                    };
+                    if self.history.moves.is_empty() {
+                        self.history.initial_colors = gs.players_colors.clone();
+                    }
+                    let color = gs.players_colors.get(&user_id).cloned().unwrap_or(NEUTRAL);
+                    let (path, player_finished) = gs.update_cones(&path, &user_id)?;
+                    self.history.record(user_id, color, path.clone());
+                    self.clock.move_made(user_id);
+                    self.active_player = next;
+                    if player_finished {
+                        self.player_finished(user_id);
+                    }
+                    self.finish_spoiled();
+                    return Ok(RoomUpdate::new_with_finished(user_id, path, self.active_player, self.game_finished));
                 }
             } else {
                 error!("Could not find user {} in cones at position: {:?}. Cones: {:?}", user_id, p, gs.cones);
@@ -149,6 +531,7 @@ impl RoomHandle {
 #[derive(Debug)]
 pub enum  Message {
     Text(String),
+    Sequenced(u64, String),
     Event(String)
 }
 
@@ -164,7 +547,16 @@ pub struct Player {
     pub name: Option<String>,
     pub sender: mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>,
     pub ready: bool,
-    pub last_active: Instant
+    pub last_active: Instant,
+    pub bot: Option<Bot>
+}
+
+// Receives the room's updates without taking a color, a turn or a place in the room.
+#[derive(Debug)]
+pub struct Spectator {
+    pub user_id: usize,
+    pub sender: mpsc::UnboundedSender<std::result::Result<Message, warp::Error>>,
+    pub last_active: Instant,
 }
 
 #[derive(Serialize)]
@@ -174,12 +566,16 @@ pub struct TokenCreatedResponse {
     pub created_at: Instant,
     pub user_id: usize,
     pub user_name: String,
+    /// Seconds since the Unix epoch.
+    pub expires_at: u64,
 }
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct User {
     pub user_id: usize,
     pub user_name: String,
+    #[serde(default)]
+    pub preferred_color: Option<usize>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -188,9 +584,42 @@ pub struct JoinRoomRequest {
     pub room_id: usize,
 }
 
+#[derive(Deserialize, Debug)]
+pub struct AddBotRequest {
+    pub name: Option<String>,
+    pub difficulty: Option<Difficulty>,
+    pub seed: Option<u64>
+}
+
 #[derive(Deserialize, Debug)]
 pub struct CreateRoomRequest {
-    pub room_name: String
+    pub room_name: String,
+    pub time_control: Option<TimeControl>,
+    pub timeout_policy: Option<TimeoutPolicy>,
+    #[serde(default)]
+    pub play_to_end: bool,
+    /// Rows in each triangle of the star, 5 by default.
+    pub board_size: Option<usize>,
+    #[serde(default)]
+    pub rules: Rules,
+    /// Colors playing together, e.g. `[[1, 4], [2, 5], [3, 6]]`, partners share their place.
+    pub teams: Option<Vec<Vec<usize>>>,
+}
+
+/// Variants of the rules a room is created with, they stay the same for the whole game.
+#[derive(Debug, Clone, Default)]
+pub struct RoomOptions {
+    pub play_to_end: bool,
+    pub board: Board,
+    pub rules: Rules,
+    pub teams: Vec<Vec<usize>>,
+}
+
+#[derive(Serialize)]
+pub struct GameStateResponse<'a> {
+    #[serde(flatten)]
+    pub game: &'a GameState,
+    pub clock: &'a GameClock,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -216,7 +645,31 @@ pub struct UpdateRoomStateRequest {
 #[derive(Serialize, Debug)]
 pub struct CreateRoomResponse {
     pub room: RoomDesc,
-    pub url: String
+    pub url: String,
+    pub links: RoomLinks
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct RoomLinks {
+    pub room: String,
+    pub sse: Option<String>,
+    pub watch: Option<String>,
+    pub ws: Option<String>,
+    pub invite: Option<String>,
+}
+
+impl RoomLinks {
+    // The event streams take the user's token as the last path segment.
+    pub fn new(config: &Config, room_id: &str, token: Option<&str>) -> RoomLinks {
+        let base = config.public_url();
+        RoomLinks {
+            room: format!("{}/room/{}", base, room_id),
+            sse: token.map(|t| format!("{}/sse/{}/{}", base, room_id, t)),
+            watch: token.map(|t| format!("{}/watch/{}/{}", base, room_id, t)),
+            ws: token.map(|t| format!("{}/ws/{}/{}", config.socket_url(), room_id, t)),
+            invite: config.invite_url(room_id),
+        }
+    }
 }
 
 #[derive(Serialize, Clone, Debug)]
@@ -231,6 +684,21 @@ pub struct RoomDesc {
     pub game_finished: bool,
     pub active_player: usize,
     pub number_of_player: usize,
+    pub spectators: usize,
+    pub forfeited: Vec<usize>,
+    pub play_to_end: bool,
+    pub standings: Vec<Standing>,
+    pub private: bool,
+    pub board_size: usize,
+    pub rules: Rules,
+    pub teams: Vec<Vec<usize>>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Standing {
+    pub user_id: usize,
+    pub place: usize,
+    pub team: Option<usize>,
 }
 
 #[derive(Debug)]
@@ -239,6 +707,14 @@ pub struct UserNotFound;
 pub struct RoomNotFound;
 #[derive(Debug)]
 pub struct RoomFull;
+#[derive(Debug)]
+pub struct InvalidRecord;
+#[derive(Debug)]
+pub struct AccountExists;
+#[derive(Debug)]
+pub struct InvalidCredentials;
+#[derive(Debug)]
+pub struct TournamentNotFound;
 
 #[derive(Serialize)]
 pub struct ErrorMessage {
@@ -251,7 +727,8 @@ pub struct PlayerDesc {
     pub name: String,
     pub color: usize,
     pub user_id: usize,
-    pub ready: bool
+    pub ready: bool,
+    pub team: Option<usize>
 }
 
 #[derive(Deserialize)]
@@ -259,17 +736,154 @@ pub struct RoomIdParameter {
     pub room_id: String
 }
 
+#[derive(Deserialize)]
+pub struct ReplayQuery {
+    pub ply: Option<usize>
+}
+
+#[derive(Deserialize)]
+pub struct QueueRequest {
+    pub players: usize,
+    pub min_rating: Option<i64>,
+    pub max_rating: Option<i64>
+}
+
+#[derive(Serialize)]
+pub struct QueueResponse {
+    pub position: Option<usize>,
+    pub room_id: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MatchFoundUpdate {
+    name: String,
+    pub room_id: String,
+    pub color: usize,
+    pub players: Vec<usize>,
+    pub links: RoomLinks,
+}
+
+impl MatchFoundUpdate {
+    pub fn new(room_id: String, color: usize, players: Vec<usize>, links: RoomLinks) -> MatchFoundUpdate {
+        MatchFoundUpdate {
+            name: "match_found".to_string(),
+            room_id,
+            color,
+            players,
+            links,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct CreateTournamentRequest {
+    pub name: String,
+    pub format: TournamentFormat,
+    pub rounds: Option<usize>
+}
+
+#[derive(Serialize)]
+pub struct TournamentDesc {
+    pub id: String,
+    pub name: String,
+    pub format: TournamentFormat,
+    pub status: TournamentStatus,
+    pub created_by: usize,
+    pub players: usize,
+    pub round: usize,
+    pub total_rounds: usize,
+}
+
+impl TournamentDesc {
+    pub fn from_tournament(t: &Tournament) -> TournamentDesc {
+        TournamentDesc {
+            id: t.id.clone(),
+            name: t.name.clone(),
+            format: t.format,
+            status: t.status,
+            created_by: t.created_by,
+            players: t.entrants.len(),
+            round: t.rounds.len(),
+            total_rounds: t.total_rounds,
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct BracketPlayer {
+    pub user_id: usize,
+    pub name: String,
+    pub seed: usize,
+}
+
+#[derive(Serialize)]
+pub struct BracketGame {
+    pub room_id: Option<String>,
+    pub players: Vec<BracketPlayer>,
+    pub winner: Option<usize>,
+    pub finished: bool,
+}
+
+/// Rounds played so far, the games of every round in bracket order.
+#[derive(Serialize)]
+pub struct BracketResponse {
+    pub format: TournamentFormat,
+    pub total_rounds: usize,
+    pub rounds: Vec<Vec<BracketGame>>,
+}
+
+impl BracketResponse {
+    pub fn from_tournament(t: &Tournament) -> BracketResponse {
+        let player = |id: &usize| {
+            let entrant = t.entrants.iter().find(|e| e.user_id == *id);
+            BracketPlayer {
+                user_id: *id,
+                name: entrant.map(|e| e.name.clone()).unwrap_or_default(),
+                seed: entrant.map(|e| e.seed).unwrap_or_default(),
+            }
+        };
+        BracketResponse {
+            format: t.format,
+            total_rounds: t.total_rounds,
+            rounds: t.rounds.iter().map(|r| r.iter().map(|p| BracketGame {
+                room_id: p.room_id.clone(),
+                players: p.players.iter().map(player).collect(),
+                winner: p.winner,
+                finished: p.finished,
+            }).collect()).collect(),
+        }
+    }
+}
+
+/// Pages start at 1.
+#[derive(Deserialize)]
+pub struct LeaderboardQuery {
+    pub page: Option<usize>,
+    pub per_page: Option<usize>
+}
+
+#[derive(Deserialize)]
+pub struct LegalMovesQuery {
+    pub row: Option<usize>,
+    pub col: Option<usize>
+}
+
 impl warp::reject::Reject for UserNotFound {}
 impl warp::reject::Reject for RoomNotFound {}
 impl warp::reject::Reject for RoomFull {}
+impl warp::reject::Reject for InvalidRecord {}
+impl warp::reject::Reject for AccountExists {}
+impl warp::reject::Reject for InvalidCredentials {}
+impl warp::reject::Reject for TournamentNotFound {}
 
 impl PlayerDesc {
-    pub fn from_player(p: &Player, color: usize) -> PlayerDesc {
+    pub fn from_player(p: &Player, color: usize, team: Option<usize>) -> PlayerDesc {
         PlayerDesc {
             name: p.name.as_ref().cloned().or_else(|| { Some("Player".to_string()) }).unwrap(),
             color,
             user_id: p.user_id,
-            ready: p.ready
+            ready: p.ready,
+            team
         }
     }
 }
@@ -286,6 +900,195 @@ impl RoomDesc {
             game_finished: rh.game_finished,
             active_player: rh.active_player,
             number_of_player: rh.players.len(),
+            spectators: rh.spectators.len(),
+            forfeited: rh.forfeited.clone(),
+            play_to_end: rh.play_to_end,
+            standings: rh.places().into_iter().map(|(user_id, place)| Standing { user_id, place, team: rh.team_of(user_id) }).collect(),
+            private: rh.private,
+            board_size: rh.history.board.size,
+            rules: rh.history.rules,
+            teams: rh.history.teams.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::game::{GREEN, PURPLE, YELLOW};
+
+    use super::*;
+
+    fn room(policy: TimeoutPolicy) -> RoomHandle {
+        let mut gs = GameState::new();
+        gs.add_cones(1, PURPLE).unwrap();
+        gs.add_cones(2, GREEN).unwrap();
+        gs.add_cones(3, YELLOW).unwrap();
+        RoomHandle {
+            room_id: "room".to_string(),
+            winner: None,
+            created_by: 1,
+            created_time: Instant::now(),
+            last_updated: Instant::now(),
+            name: "test".to_string(),
+            game_started: true,
+            game_finished: false,
+            active_player: 0,
+            game_state: Some(gs),
+            players: (1..=3).map(|id| Bot::player(id, format!("player{}", id), Bot::new(Difficulty::Easy, 0))).collect(),
+            spectators: Vec::new(),
+            history: GameHistory::default(),
+            events: EventLog::default(),
+            clock: GameClock::new(TimeControl::default(), policy),
+            forfeited: Vec::new(),
+            walked_out: Vec::new(),
+            results: None,
+            play_to_end: false,
+            standings: Vec::new(),
+            private: false,
+            reserved: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_event_log_is_bounded() {
+        let mut log = EventLog::with_last_id(10);
+        assert_eq!(None, log.first_id());
+        for i in 0..EVENT_LOG_SIZE + 5 {
+            assert_eq!(11 + i as u64, log.push(format!("event {}", i)));
+        }
+        assert_eq!(Some(16), log.first_id());
+        assert_eq!(EVENT_LOG_SIZE, log.since(0).count());
+        let missed: Vec<u64> = log.since(EVENT_LOG_SIZE as u64 + 12).map(|(id, _)| *id).collect();
+        assert_eq!(vec![EVENT_LOG_SIZE as u64 + 13, EVENT_LOG_SIZE as u64 + 14, EVENT_LOG_SIZE as u64 + 15], missed);
+    }
+
+    #[test]
+    fn test_forfeit_after_repeated_timeouts() {
+        let mut r = room(TimeoutPolicy { action: TimeoutAction::Skip, forfeit_after: Some(2) });
+        // Humans are rated, the bot is not.
+        r.players[1].bot = None;
+        r.players[2].bot = None;
+        let (sender, mut results) = mpsc::unbounded_channel();
+        r.results = Some(sender);
+        for _ in 0..3 {
+            let (update, auto_move) = r.time_out();
+            assert!(!update.forfeited);
+            assert!(auto_move.is_none());
         }
+        assert_eq!(0, r.active_player);
+        let (update, _) = r.time_out();
+        assert!(update.forfeited);
+        assert_eq!(vec![1], r.forfeited);
+        assert!(r.game_state.as_ref().unwrap().get_cones(&1).is_empty());
+        assert_eq!(1, r.active_player);
+        assert_eq!(2, r.next_active_player());
+        r.active_player = 2;
+        assert_eq!(1, r.next_active_player());
+
+        let (update, _) = r.time_out();
+        assert_eq!(2, update.consecutive_timeouts);
+        assert!(update.forfeited);
+        assert!(r.game_finished);
+        assert_eq!(Some(2), r.winner);
+        let result = results.try_recv().unwrap();
+        assert_eq!(vec![(2, 1), (3, 2)], result.places);
+        assert!(r.results.is_none());
+    }
+
+    #[test]
+    fn test_forfeit_is_replayed() {
+        let mut r = room(TimeoutPolicy::default());
+        r.make_a_move(vec![(4, 0), (5, 5)], 1, false).unwrap();
+        r.forfeit(2);
+        r.make_a_move(vec![(16, 0), (15, 5)], 3, false).unwrap();
+        assert_eq!(vec![(2, 1)], r.history.forfeits);
+        let replayed = r.history.replay(r.history.moves.len()).unwrap();
+        assert_eq!(r.game_state.as_ref().unwrap().cones, replayed.cones);
+        assert!(r.history.replay(0).unwrap().cones.values().any(|id| *id == 2));
+    }
+
+    #[test]
+    fn test_abandoned_room_sends_a_result() {
+        let mut r = room(TimeoutPolicy::default());
+        let (sender, mut results) = mpsc::unbounded_channel();
+        r.results = Some(sender);
+        r.walked_out.push(2);
+        r.players.clear();
+        r.abandon();
+        assert!(r.game_finished);
+        let result = results.try_recv().unwrap();
+        assert_eq!(None, result.winner);
+        assert_eq!(vec![2], result.forfeited);
+        assert!(!result.is_rated());
+        r.abandon();
+        assert!(results.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_play_to_end() {
+        let mut r = room(TimeoutPolicy::default());
+        r.player_finished(1);
+        assert!(r.game_finished);
+        assert_eq!(vec![(1, 1), (2, 2), (3, 2)], r.places());
+
+        let mut r = room(TimeoutPolicy::default());
+        r.play_to_end = true;
+        r.player_finished(2);
+        assert!(!r.game_finished);
+        assert_eq!(vec![(2, 1)], r.places());
+        r.active_player = 0;
+        assert_eq!(2, r.next_active_player());
+        r.player_finished(3);
+        assert!(r.game_finished);
+        assert_eq!(Some(2), r.winner);
+        assert_eq!(vec![(2, 1), (3, 2), (1, 3)], r.places());
+    }
+
+    #[test]
+    fn test_spoiled_destination() {
+        let mut r = room(TimeoutPolicy::default());
+        r.play_to_end = true;
+        let gs = r.game_state.as_mut().unwrap();
+        gs.rules.spoiler = true;
+        gs.cones.insert((16, 0), 1);
+        gs.cones.remove(&(16, 1));
+        gs.cones.insert((15, 6), 3);
+        r.active_player = 2;
+        let update = r.make_a_move(vec![(15, 6), (16, 1)], 3, false).unwrap();
+        assert_eq!(vec![1], r.standings);
+        assert!(!r.game_finished);
+        assert_eq!(1, r.active_player);
+        assert_eq!(1, update.next_player);
+    }
+
+    #[test]
+    fn test_team_finishes_together() {
+        let mut r = room(TimeoutPolicy::default());
+        let gs = r.game_state.as_mut().unwrap();
+        gs.teams = vec![vec![PURPLE, YELLOW]];
+        gs.cones.retain(|_, id| *id != 3);
+        r.player_finished(3);
+        assert!(r.standings.is_empty());
+        // Waits for the partner without moving.
+        r.active_player = 1;
+        assert_eq!(0, r.next_active_player());
+        r.player_finished(1);
+        assert!(r.game_finished);
+        assert_eq!(Some(1), r.winner);
+        assert_eq!(vec![(1, 1), (3, 1), (2, 3)], r.places());
+        let standings = RoomDesc::from_room(&r).standings;
+        assert_eq!(Standing { user_id: 3, place: 1, team: Some(0) }, standings[1]);
+        assert_eq!(None, standings[2].team);
+    }
+
+    #[test]
+    fn test_auto_move_on_timeout() {
+        let mut r = room(TimeoutPolicy { action: TimeoutAction::AutoMove, forfeit_after: None });
+        let (update, auto_move) = r.time_out();
+        assert_eq!(TimeoutAction::AutoMove, update.action);
+        assert_eq!(1, auto_move.unwrap().by_user_id);
+        assert_eq!(1, r.history.moves.len());
+        assert_eq!(1, r.active_player);
+        assert_eq!(1, r.clock.timeouts[&1]);
     }
 }
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::model::RoomHandle;
+use crate::stats::PlayerGame;
+
+pub const INITIAL_RATING: f64 = 1500.0;
+const K_FACTOR: f64 = 32.0;
+
+pub type ResultSender = mpsc::UnboundedSender<GameResult>;
+
+/// Outcome of a finished game, places start at 1 and players sharing a place drew with each other.
+/// Bots are left out, their games only count against other people.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GameResult {
+    pub room_id: String,
+    pub winner: Option<usize>,
+    pub places: Vec<(usize, usize)>,
+    pub players: Vec<PlayerGame>,
+    /// Players who forfeited or walked out of the game.
+    pub forfeited: Vec<usize>,
+}
+
+impl GameResult {
+    pub fn from_room(rh: &RoomHandle) -> GameResult {
+        let places: Vec<(usize, usize)> = rh.places().into_iter()
+            .filter(|(id, _)| rh.players.iter().any(|p| p.user_id == *id && p.bot.is_none()))
+            .collect();
+        let players = places.iter().map(|(id, place)| {
+            let name = rh.players.iter().find(|p| p.user_id == *id).and_then(|p| p.name.clone());
+            // Cones of the players who forfeited are off the board, so they would count as in place.
+            let finished = !rh.forfeited.contains(id)
+                && rh.game_state.as_ref().is_some_and(|gs| gs.is_all_cones_in_place(id).unwrap_or(false));
+            PlayerGame::from_history(&rh.history, *id, name, *place, finished)
+        }).collect();
+        let forfeited = rh.forfeited.iter().chain(rh.walked_out.iter()).cloned().collect();
+        GameResult { room_id: rh.room_id.clone(), winner: rh.winner, places, players, forfeited }
+    }
+
+    pub fn is_rated(&self) -> bool {
+        self.places.len() > 1
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RatingChange {
+    pub room_id: String,
+    #[serde(with = "serde_millis")]
+    pub time: SystemTime,
+    pub place: usize,
+    pub players: usize,
+    pub rating_before: f64,
+    pub rating: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Rating {
+    pub user_id: usize,
+    pub rating: f64,
+    pub games: usize,
+    pub history: Vec<RatingChange>,
+}
+
+impl Rating {
+    pub fn new(user_id: usize) -> Rating {
+        Rating { user_id, rating: INITIAL_RATING, games: 0, history: Vec::new() }
+    }
+}
+
+/// Elo ratings. A game of n players counts as n - 1 games against each of the opponents,
+/// with the K factor split between them, so a win takes the same weight in any room size.
+#[derive(Debug, Default)]
+pub struct Ratings {
+    players: HashMap<usize, Rating>,
+}
+
+impl Ratings {
+    pub fn get(&self, user_id: usize) -> Rating {
+        self.players.get(&user_id).cloned().unwrap_or_else(|| Rating::new(user_id))
+    }
+
+    pub fn all(&self) -> impl Iterator<Item=&Rating> {
+        self.players.values()
+    }
+
+    pub fn restore(&mut self, ratings: Vec<Rating>) {
+        self.players.extend(ratings.into_iter().map(|r| (r.user_id, r)));
+    }
+
+    pub fn apply(&mut self, result: &GameResult) {
+        if !result.is_rated() {
+            return;
+        }
+        let before: Vec<f64> = result.places.iter().map(|(id, _)| self.get(*id).rating).collect();
+        let k = K_FACTOR / (result.places.len() - 1) as f64;
+        let time = SystemTime::now();
+        for (i, (user_id, place)) in result.places.iter().enumerate() {
+            let delta: f64 = result.places.iter().enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(j, (_, other_place))| score(*place, *other_place) - expected(before[i], before[j]))
+                .sum();
+            let rating = self.players.entry(*user_id).or_insert_with(|| Rating::new(*user_id));
+            rating.rating = before[i] + k * delta;
+            rating.games += 1;
+            rating.history.push(RatingChange {
+                room_id: result.room_id.clone(),
+                time,
+                place: *place,
+                players: result.places.len(),
+                rating_before: before[i],
+                rating: rating.rating,
+            });
+        }
+    }
+}
+
+fn score(place: usize, other_place: usize) -> f64 {
+    if place < other_place {
+        1.0
+    } else if place == other_place {
+        0.5
+    } else {
+        0.0
+    }
+}
+
+fn expected(rating: f64, other_rating: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf((other_rating - rating) / 400.0))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(places: Vec<(usize, usize)>) -> GameResult {
+        GameResult { room_id: "room".to_string(), winner: None, places, players: Vec::new(), forfeited: Vec::new() }
+    }
+
+    #[test]
+    fn test_two_player_game() {
+        let mut ratings = Ratings::default();
+        ratings.apply(&result(vec![(1, 1), (2, 2)]));
+        assert_eq!(1516.0, ratings.get(1).rating);
+        assert_eq!(1484.0, ratings.get(2).rating);
+        assert_eq!(1, ratings.get(2).games);
+        assert_eq!(1500.0, ratings.get(2).history[0].rating_before);
+
+        // Beating a weaker player again gives less.
+        ratings.apply(&result(vec![(1, 1), (2, 2)]));
+        assert!(ratings.get(1).rating - 1516.0 < 16.0);
+        assert_eq!(0, ratings.get(3).games);
+    }
+
+    #[test]
+    fn test_multiplayer_game() {
+        let mut ratings = Ratings::default();
+        ratings.apply(&result(vec![(1, 1), (2, 2), (3, 2), (4, 3)]));
+        let r: Vec<f64> = (1..=4).map(|id| ratings.get(id).rating).collect();
+        assert_eq!(1516.0, r[0]);
+        assert_eq!(r[1], r[2]);
+        assert_eq!(1500.0, r[1]);
+        assert_eq!(1484.0, r[3]);
+        assert!((r.iter().sum::<f64>() - 6000.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_single_player_is_not_rated() {
+        let mut ratings = Ratings::default();
+        ratings.apply(&result(vec![(1, 1)]));
+        assert_eq!(0, ratings.all().count());
+    }
+}
@@ -0,0 +1,393 @@
+//! Textual game record format, loosely modelled after chess PGN.
+//!
+//! A record starts with header tags, one per line, in the form `[Name "value"]`:
+//!
+//! * `Event` - always `Sterligov game`,
+//! * `Room` and `RoomId` - name and id of the room the game was played in,
+//! * `Started` and `Finished` - milliseconds since the Unix epoch of the first and the last move, `?` if unknown,
+//! * one tag per taking part color (`Purple`, `Green`, `Orange`, `Yellow`, `Red`, `Blue`) with the value
+//!   `<user_id> <name>`,
+//! * `Board` - number of rows in each triangle of the star, 5 if missing,
+//! * `Rules` - house rules the game was played with, separated by commas: `super-jumps`, `no-parking`,
+//!   `spoiler`, `no-leaving-destination` and `partner-jumps-only`,
+//! * `Teams` - colors playing together, teams separated by commas, e.g. `Purple Yellow, Green Red`,
+//! * `Forfeits` - players who forfeited, as the color and the number of moves made before, separated by commas,
+//!   e.g. `Green 12, Red 30`,
+//! * `Result` - color of the winner, or `*` if the game is not finished.
+//!
+//! Moves follow the header, one per line: the ply number, the color letter (`P`, `G`, `O`, `Y`, `R`, `B`),
+//! the visited points as `row,col` joined by `-`, and an optional timestamp in curly braces:
+//!
+//! ```text
+//! [Event "Sterligov game"]
+//! [Room "Friday"]
+//! [Purple "0 alice"]
+//! [Yellow "1 bob"]
+//! [Result "*"]
+//!
+//! 1. P 4,0-5,6 {1602956400000}
+//! 2. Y 16,4-15,10 {1602956405000}
+//! 3. P 3,0-5,5-5,7
+//! ```
+//!
+//! Empty lines and lines starting with `;` are ignored.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::board::Board;
+use crate::game::{BLUE, GameHistory, GREEN, MoveRecord, ORANGE, PURPLE, RED, Rules, YELLOW};
+use crate::model::RoomHandle;
+
+const EVENT: &str = "Sterligov game";
+const COLORS: [(usize, &str, char); 6] = [
+    (PURPLE, "Purple", 'P'),
+    (GREEN, "Green", 'G'),
+    (ORANGE, "Orange", 'O'),
+    (YELLOW, "Yellow", 'Y'),
+    (RED, "Red", 'R'),
+    (BLUE, "Blue", 'B'),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordPlayer {
+    pub user_id: usize,
+    pub color: usize,
+    pub name: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct GameRecord {
+    pub room_name: String,
+    pub room_id: Option<String>,
+    pub players: Vec<RecordPlayer>,
+    pub board: Board,
+    pub rules: Rules,
+    pub teams: Vec<Vec<usize>>,
+    // (user_id, ply) of the players who forfeited.
+    pub forfeits: Vec<(usize, usize)>,
+    // Color of the winner.
+    pub result: Option<usize>,
+    pub moves: Vec<MoveRecord>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct RecordParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl GameRecord {
+    pub fn from_room(rh: &RoomHandle) -> GameRecord {
+        let mut players: Vec<RecordPlayer> = rh.history.initial_colors.iter()
+            .map(|(user_id, color)| RecordPlayer {
+                user_id: *user_id,
+                color: *color,
+                name: rh.players.iter().find(|p| p.user_id == *user_id)
+                    .and_then(|p| p.name.clone())
+                    .unwrap_or_else(|| "Player".to_string()),
+            })
+            .collect();
+        players.sort_by_key(|p| p.color);
+        GameRecord {
+            room_name: rh.name.clone(),
+            room_id: Some(rh.room_id.clone()),
+            result: rh.winner.and_then(|w| rh.history.initial_colors.get(&w).cloned()),
+            players,
+            board: rh.history.board,
+            rules: rh.history.rules,
+            teams: rh.history.teams.clone(),
+            forfeits: rh.history.forfeits.clone(),
+            moves: rh.history.moves.clone(),
+        }
+    }
+
+    pub fn to_history(&self) -> GameHistory {
+        GameHistory {
+            board: self.board,
+            rules: self.rules,
+            teams: self.teams.clone(),
+            initial_colors: self.players.iter().map(|p| (p.user_id, p.color)).collect(),
+            moves: self.moves.clone(),
+            forfeits: self.forfeits.clone(),
+        }
+    }
+
+    pub fn winner(&self) -> Option<usize> {
+        self.result.and_then(|color| self.players.iter().find(|p| p.color == color).map(|p| p.user_id))
+    }
+
+    pub fn parse(text: &str) -> std::result::Result<GameRecord, RecordParseError> {
+        // (line, name, value)
+        let mut tags: Vec<(usize, String, String)> = Vec::new();
+        let mut move_lines = Vec::new();
+        for (ind, raw) in text.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            if line.starts_with('[') {
+                if !move_lines.is_empty() {
+                    return Err(error(ind, "header tag after the first move"));
+                }
+                let (name, value) = parse_tag(ind, line)?;
+                tags.push((ind, name, value));
+            } else {
+                move_lines.push((ind, line));
+            }
+        }
+        let tag = |name: &str| tags.iter().find(|(_, n, _)| n == name).map(|(_, _, v)| v.as_str());
+        // Errors in a tag point at the line it came from.
+        let tag_error = |name: &str, message: &str| {
+            let ind = tags.iter().find(|(_, n, _)| n == name).map_or(0, |(ind, _, _)| *ind);
+            error(ind, message)
+        };
+
+        let mut players = Vec::new();
+        for (color, name, _) in COLORS.iter() {
+            if let Some(value) = tag(name) {
+                let mut parts = value.splitn(2, ' ');
+                let user_id = parts.next().and_then(|id| id.parse::<usize>().ok())
+                    .ok_or_else(|| tag_error(name, &format!("invalid user id for {}", name)))?;
+                players.push(RecordPlayer { user_id, color: *color, name: parts.next().unwrap_or("").to_string() });
+            }
+        }
+        let by_color: HashMap<char, &RecordPlayer> = players.iter()
+            .filter_map(|p| color_letter(p.color).map(|l| (l, p)))
+            .collect();
+
+        let mut moves = Vec::new();
+        for (ind, line) in move_lines {
+            let mut parts = line.split_whitespace();
+            let ply = parts.next().and_then(|p| p.strip_suffix('.')).and_then(|p| p.parse::<usize>().ok());
+            if ply != Some(moves.len() + 1) {
+                return Err(error(ind, "expected the ply number"));
+            }
+            let player = parts.next().and_then(|c| c.chars().next())
+                .and_then(|c| by_color.get(&c))
+                .ok_or_else(|| error(ind, "unknown color"))?;
+            let path = parts.next().and_then(parse_path).ok_or_else(|| error(ind, "invalid path"))?;
+            let timestamp = match parts.next() {
+                Some(t) => t.strip_prefix('{').and_then(|t| t.strip_suffix('}'))
+                    .and_then(parse_time)
+                    .ok_or_else(|| error(ind, "invalid timestamp"))?,
+                None => UNIX_EPOCH
+            };
+            if parts.next().is_some() {
+                return Err(error(ind, "unexpected text after the move"));
+            }
+            moves.push(MoveRecord { user_id: player.user_id, color: player.color, path, timestamp });
+        }
+
+        let board = match tag("Board") {
+            None => Board::default(),
+            Some(b) => b.parse::<usize>().ok().and_then(Board::new).ok_or_else(|| tag_error("Board", "invalid board size"))?
+        };
+        let mut rules = Rules::default();
+        for rule in tag("Rules").unwrap_or("").split(',').map(|r| r.trim()).filter(|r| !r.is_empty()) {
+            if !rules.enable(rule) {
+                return Err(tag_error("Rules", "unknown rule"));
+            }
+        }
+        let teams = tag("Teams").unwrap_or("").split(',').map(|t| t.trim()).filter(|t| !t.is_empty())
+            .map(|t| t.split_whitespace()
+                .map(|name| COLORS.iter().find(|(_, n, _)| *n == name).map(|(c, _, _)| *c))
+                .collect::<Option<Vec<usize>>>())
+            .collect::<Option<Vec<Vec<usize>>>>()
+            .ok_or_else(|| tag_error("Teams", "invalid teams"))?;
+        let mut forfeits = Vec::new();
+        for forfeit in tag("Forfeits").unwrap_or("").split(',').map(|f| f.trim()).filter(|f| !f.is_empty()) {
+            let mut parts = forfeit.split_whitespace();
+            let player = parts.next().and_then(|c| COLORS.iter().find(|(_, name, _)| *name == c))
+                .and_then(|(color, _, _)| players.iter().find(|p| p.color == *color));
+            let ply = parts.next().and_then(|p| p.parse::<usize>().ok()).filter(|p| *p <= moves.len());
+            match (player, ply, parts.next()) {
+                (Some(player), Some(ply), None) => forfeits.push((player.user_id, ply)),
+                _ => return Err(tag_error("Forfeits", "invalid forfeit"))
+            }
+        }
+        let result = match tag("Result") {
+            None | Some("*") => None,
+            Some(r) => Some(COLORS.iter().find(|(_, name, _)| *name == r).map(|(c, _, _)| *c)
+                .ok_or_else(|| tag_error("Result", "invalid result"))?)
+        };
+        Ok(GameRecord {
+            room_name: tag("Room").unwrap_or("").to_string(),
+            room_id: tag("RoomId").map(|r| r.to_string()),
+            players,
+            board,
+            rules,
+            teams,
+            forfeits,
+            result,
+            moves,
+        })
+    }
+}
+
+impl fmt::Display for GameRecord {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "[Event \"{}\"]", EVENT)?;
+        writeln!(f, "[Room \"{}\"]", escape(&self.room_name))?;
+        if let Some(id) = &self.room_id {
+            writeln!(f, "[RoomId \"{}\"]", escape(id))?;
+        }
+        writeln!(f, "[Started \"{}\"]", format_time(self.moves.first().map(|m| m.timestamp)))?;
+        let finished = self.result.and(self.moves.last().map(|m| m.timestamp));
+        writeln!(f, "[Finished \"{}\"]", format_time(finished))?;
+        for p in self.players.iter() {
+            if let Some(name) = color_name(p.color) {
+                writeln!(f, "[{} \"{} {}\"]", name, p.user_id, escape(&p.name))?;
+            }
+        }
+        writeln!(f, "[Board \"{}\"]", self.board.size)?;
+        if self.rules != Rules::default() {
+            writeln!(f, "[Rules \"{}\"]", self.rules.names().join(","))?;
+        }
+        if !self.teams.is_empty() {
+            let teams: Vec<String> = self.teams.iter()
+                .map(|t| t.iter().filter_map(|c| color_name(*c)).collect::<Vec<&str>>().join(" "))
+                .collect();
+            writeln!(f, "[Teams \"{}\"]", teams.join(", "))?;
+        }
+        if !self.forfeits.is_empty() {
+            let forfeits: Vec<String> = self.forfeits.iter()
+                .filter_map(|(id, ply)| self.players.iter().find(|p| p.user_id == *id)
+                    .and_then(|p| color_name(p.color))
+                    .map(|name| format!("{} {}", name, ply)))
+                .collect();
+            writeln!(f, "[Forfeits \"{}\"]", forfeits.join(", "))?;
+        }
+        writeln!(f, "[Result \"{}\"]", self.result.and_then(color_name).unwrap_or("*"))?;
+        writeln!(f)?;
+        for (ind, m) in self.moves.iter().enumerate() {
+            let path: Vec<String> = m.path.iter().map(|(r, c)| format!("{},{}", r, c)).collect();
+            writeln!(f, "{}. {} {} {{{}}}", ind + 1, color_letter(m.color).unwrap_or('?'), path.join("-"), format_time(Some(m.timestamp)))?;
+        }
+        Ok(())
+    }
+}
+
+fn error(line: usize, message: &str) -> RecordParseError {
+    RecordParseError { line: line + 1, message: message.to_string() }
+}
+
+fn color_name(color: usize) -> Option<&'static str> {
+    COLORS.iter().find(|(c, _, _)| *c == color).map(|(_, name, _)| *name)
+}
+
+fn color_letter(color: usize) -> Option<char> {
+    COLORS.iter().find(|(c, _, _)| *c == color).map(|(_, _, letter)| *letter)
+}
+
+// Quotes would end the tag value early, so they are dropped together with line breaks.
+fn escape(value: &str) -> String {
+    value.chars().filter(|c| *c != '"' && *c != '\n' && *c != '\r').collect()
+}
+
+fn parse_tag(ind: usize, line: &str) -> std::result::Result<(String, String), RecordParseError> {
+    let inner = line.strip_prefix('[').and_then(|l| l.strip_suffix(']'))
+        .ok_or_else(|| error(ind, "invalid header tag"))?;
+    let mut parts = inner.splitn(2, ' ');
+    let name = parts.next().unwrap_or("");
+    let value = parts.next().map(|v| v.trim())
+        .and_then(|v| v.strip_prefix('"')).and_then(|v| v.strip_suffix('"'))
+        .ok_or_else(|| error(ind, "header tag value must be quoted"))?;
+    if name.is_empty() {
+        return Err(error(ind, "header tag without a name"));
+    }
+    Ok((name.to_string(), value.to_string()))
+}
+
+fn parse_path(path: &str) -> Option<Vec<(usize, usize)>> {
+    let points: Option<Vec<(usize, usize)>> = path.split('-')
+        .map(|point| {
+            let mut coords = point.split(',').map(|c| c.parse::<usize>().ok());
+            match (coords.next().flatten(), coords.next().flatten(), coords.next()) {
+                (Some(row), Some(col), None) => Some((row, col)),
+                _ => None
+            }
+        })
+        .collect();
+    points.filter(|p| p.len() >= 2)
+}
+
+fn format_time(time: Option<SystemTime>) -> String {
+    time.and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_millis().to_string())
+        .unwrap_or_else(|| "?".to_string())
+}
+
+fn parse_time(value: &str) -> Option<SystemTime> {
+    value.parse::<u64>().ok().map(|ms| UNIX_EPOCH + Duration::from_millis(ms))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: &str = r#"[Event "Sterligov game"]
+[Room "Friday"]
+[Purple "0 alice"]
+[Yellow "1 bob smith"]
+[Result "Purple"]
+
+; opening
+1. P 4,0-5,6 {1602956400000}
+2. Y 16,4-15,10 {1602956405000}
+3. P 3,0-4,0
+"#;
+
+    #[test]
+    fn test_parse() {
+        let record = GameRecord::parse(RECORD).unwrap();
+        assert_eq!("Friday", record.room_name);
+        assert_eq!(vec![
+            RecordPlayer { user_id: 0, color: PURPLE, name: "alice".to_string() },
+            RecordPlayer { user_id: 1, color: YELLOW, name: "bob smith".to_string() },
+        ], record.players);
+        assert_eq!(Some(0), record.winner());
+        assert_eq!(3, record.moves.len());
+        assert_eq!(1, record.moves[1].user_id);
+        assert_eq!(vec![(16, 4), (15, 10)], record.moves[1].path);
+        assert_eq!(UNIX_EPOCH + Duration::from_millis(1602956405000), record.moves[1].timestamp);
+        assert!(record.to_history().replay(3).is_ok());
+        // Yellow moving the purple cone is caught at its ply.
+        let stolen = GameRecord::parse(&RECORD.replace("2. Y 16,4-15,10", "2. Y 5,6-6,6")).unwrap();
+        assert_eq!(Err(1), stolen.to_history().replay(3).map(|_| ()));
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let record = GameRecord::parse(RECORD).unwrap();
+        let reparsed = GameRecord::parse(&record.to_string()).unwrap();
+        assert_eq!(record.players, reparsed.players);
+        assert_eq!(record.result, reparsed.result);
+        assert_eq!(Board::default(), reparsed.board);
+        assert_eq!(Rules::default(), reparsed.rules);
+        let rules = GameRecord::parse(&RECORD.replace("[Result", "[Rules \"super-jumps, spoiler\"]\n[Result")).unwrap();
+        assert_eq!(Rules { super_jumps: true, spoiler: true, ..Rules::default() }, GameRecord::parse(&rules.to_string()).unwrap().rules);
+        let teams = GameRecord::parse(&RECORD.replace("[Result", "[Teams \"Purple Yellow, Green Red\"]\n[Result")).unwrap();
+        assert_eq!(vec![vec![PURPLE, YELLOW], vec![GREEN, RED]], GameRecord::parse(&teams.to_string()).unwrap().teams);
+        let forfeits = GameRecord::parse(&RECORD.replace("[Result", "[Forfeits \"Yellow 2\"]\n[Result")).unwrap();
+        let reparsed_forfeits = GameRecord::parse(&forfeits.to_string()).unwrap();
+        assert_eq!(vec![(1, 2)], reparsed_forfeits.forfeits);
+        assert!(reparsed_forfeits.to_history().replay(3).unwrap().cones.values().all(|id| *id == 0));
+        let paths: Vec<_> = reparsed.moves.iter().map(|m| (m.user_id, m.path.clone(), m.timestamp)).collect();
+        assert_eq!(record.moves.iter().map(|m| (m.user_id, m.path.clone(), m.timestamp)).collect::<Vec<_>>(), paths);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_eq!(10, GameRecord::parse(&RECORD.replace("3. P", "4. P")).unwrap_err().line);
+        assert_eq!("unknown color", GameRecord::parse(&RECORD.replace("2. Y", "2. G")).unwrap_err().message);
+        assert_eq!("invalid path", GameRecord::parse(&RECORD.replace("4,0-5,6", "4,0")).unwrap_err().message);
+        assert_eq!(3, GameRecord::parse(&RECORD.replace("[Purple \"0 alice\"]", "[Purple 0]")).unwrap_err().line);
+        let board = GameRecord::parse(&RECORD.replace("[Result", "[Board \"2\"]\n[Result")).unwrap_err();
+        assert_eq!(RecordParseError { line: 5, message: "invalid board size".to_string() }, board);
+        assert_eq!(4, GameRecord::parse(&RECORD.replace("1 bob smith", "x bob")).unwrap_err().line);
+        assert_eq!("invalid forfeit", GameRecord::parse(&RECORD.replace("[Result", "[Forfeits \"Green 1\"]\n[Result")).unwrap_err().message);
+        assert_eq!(5, GameRecord::parse(&RECORD.replace("[Result \"Purple\"]", "[Result \"Pink\"]")).unwrap_err().line);
+    }
+}
@@ -0,0 +1,190 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac, NewMac};
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use uuid::Uuid;
+
+use crate::model::User;
+
+const SECRET_LEN: usize = 32;
+const REVOKED_SHARDS: usize = 16;
+
+/// What a session token says about its holder, signed by the server.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Claims {
+    pub uid: usize,
+    pub name: String,
+    #[serde(default)]
+    pub color: Option<usize>,
+    /// Expiry time in seconds since the Unix epoch.
+    pub exp: u64,
+    pub jti: String,
+}
+
+impl Claims {
+    pub fn user(&self) -> User {
+        User {
+            user_id: self.uid,
+            user_name: self.name.clone(),
+            preferred_color: self.color,
+        }
+    }
+}
+
+/// Issues and checks `<payload>.<signature>` tokens, both parts base64url encoded,
+/// the signature is HMAC-SHA256 of the payload. Only logged out tokens are kept,
+/// until they expire. They are split into shards by token id, so checking a token
+/// only ever waits for a logout that lands in the same shard.
+pub struct Sessions {
+    secret: Vec<u8>,
+    ttl: Duration,
+    revoked: Vec<RwLock<HashMap<String, u64>>>,
+}
+
+impl Sessions {
+    pub fn new(secret: Vec<u8>, ttl: Duration) -> Sessions {
+        Sessions {
+            secret,
+            ttl,
+            revoked: (0..REVOKED_SHARDS).map(|_| RwLock::new(HashMap::new())).collect(),
+        }
+    }
+
+    pub fn random_secret() -> Vec<u8> {
+        let mut secret = vec![0u8; SECRET_LEN];
+        rand::thread_rng().fill_bytes(&mut secret);
+        secret
+    }
+
+    pub fn issue(&self, user: &User) -> (String, Claims) {
+        let claims = Claims {
+            uid: user.user_id,
+            name: user.user_name.clone(),
+            color: user.preferred_color,
+            exp: now() + self.ttl.as_secs(),
+            jti: Uuid::new_v4().simple().to_string(),
+        };
+        let payload = serde_json::to_vec(&claims).expect("Claims are serializable");
+        let signature = self.mac(&payload).finalize().into_bytes();
+        let token = format!("{}.{}", encode(&payload), encode(&signature));
+        (token, claims)
+    }
+
+    pub fn verify(&self, token: &str) -> Option<Claims> {
+        let mut parts = token.splitn(2, '.');
+        let payload = decode(parts.next()?)?;
+        let signature = decode(parts.next()?)?;
+        self.mac(&payload).verify(&signature).ok()?;
+        let claims: Claims = serde_json::from_slice(&payload).ok()?;
+        if claims.exp <= now() || self.shard(&claims.jti).read().unwrap().contains_key(&claims.jti) {
+            None
+        } else {
+            Some(claims)
+        }
+    }
+
+    pub fn revoke(&self, claims: &Claims) {
+        let mut revoked = self.shard(&claims.jti).write().unwrap();
+        let now = now();
+        revoked.retain(|_, exp| *exp > now);
+        revoked.insert(claims.jti.clone(), claims.exp);
+    }
+
+    pub fn revoked(&self) -> Vec<(String, u64)> {
+        self.revoked.iter()
+            .flat_map(|shard| shard.read().unwrap().iter().map(|(jti, exp)| (jti.clone(), *exp)).collect::<Vec<_>>())
+            .collect()
+    }
+
+    pub fn restore_revoked(&self, revoked: Vec<(String, u64)>) {
+        let now = now();
+        for (jti, exp) in revoked.into_iter().filter(|(_, exp)| *exp > now) {
+            self.shard(&jti).write().unwrap().insert(jti, exp);
+        }
+    }
+
+    fn shard(&self, jti: &str) -> &RwLock<HashMap<String, u64>> {
+        let hash = jti.bytes().fold(0usize, |h, b| h.wrapping_mul(31).wrapping_add(b as usize));
+        &self.revoked[hash % REVOKED_SHARDS]
+    }
+
+    fn mac(&self, payload: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_varkey(&self.secret).expect("HMAC takes keys of any size");
+        mac.update(payload);
+        mac
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(text: &str) -> Option<Vec<u8>> {
+    base64::decode_config(text, base64::URL_SAFE_NO_PAD).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn user() -> User {
+        User { user_id: 7, user_name: "alice".to_string(), preferred_color: Some(3) }
+    }
+
+    #[test]
+    fn test_issue_and_verify() {
+        let sessions = Sessions::new(b"secret".to_vec(), Duration::from_secs(60));
+        let (token, claims) = sessions.issue(&user());
+        assert_eq!(Some(claims), sessions.verify(&token));
+        assert_eq!(Some(3), sessions.verify(&token).unwrap().user().preferred_color);
+
+        let other = Sessions::new(b"other secret".to_vec(), Duration::from_secs(60));
+        assert_eq!(None, other.verify(&token));
+        assert_eq!(None, sessions.verify("not a token"));
+    }
+
+    #[test]
+    fn test_tampered_token_is_rejected() {
+        let sessions = Sessions::new(b"secret".to_vec(), Duration::from_secs(60));
+        let (token, mut claims) = sessions.issue(&user());
+        claims.uid = 1;
+        let signature = token.split('.').nth(1).unwrap();
+        let forged = format!("{}.{}", encode(&serde_json::to_vec(&claims).unwrap()), signature);
+        assert_eq!(None, sessions.verify(&forged));
+    }
+
+    #[test]
+    fn test_expired_and_revoked_tokens() {
+        let expired = Sessions::new(b"secret".to_vec(), Duration::from_secs(0));
+        let (token, _) = expired.issue(&user());
+        assert_eq!(None, expired.verify(&token));
+
+        let sessions = Sessions::new(b"secret".to_vec(), Duration::from_secs(60));
+        let (token, claims) = sessions.issue(&user());
+        sessions.revoke(&claims);
+        assert_eq!(None, sessions.verify(&token));
+
+        let restarted = Sessions::new(b"secret".to_vec(), Duration::from_secs(60));
+        restarted.restore_revoked(sessions.revoked());
+        assert_eq!(None, restarted.verify(&token));
+
+        // Logouts spread over the shards, the other tokens stay valid.
+        let issued: Vec<(String, Claims)> = (0..40).map(|_| sessions.issue(&user())).collect();
+        for (_, claims) in issued.iter().step_by(2) {
+            sessions.revoke(claims);
+        }
+        assert_eq!(21, sessions.revoked().len());
+        assert!(sessions.revoked.iter().filter(|s| !s.read().unwrap().is_empty()).count() > 1);
+        for (i, (token, _)) in issued.iter().enumerate() {
+            assert_eq!(i % 2 == 1, sessions.verify(token).is_some());
+        }
+    }
+}
@@ -0,0 +1,187 @@
+use std::collections::{BTreeMap, HashMap};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::board::Board;
+use crate::game::GameHistory;
+use crate::ratings::GameResult;
+
+/// What a player did in one game, taken from the room's history when the game is over.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerGame {
+    pub user_id: usize,
+    pub name: Option<String>,
+    pub place: usize,
+    /// All cones reached the opposite triangle.
+    pub finished: bool,
+    pub moves: usize,
+    /// Time between the previous move in the room and the player's move, summed over `timed_moves`.
+    /// The first move of a game has nothing to be measured from.
+    pub move_time_ms: u64,
+    pub timed_moves: usize,
+    pub longest_chain: usize,
+}
+
+impl PlayerGame {
+    pub fn from_history(history: &GameHistory, user_id: usize, name: Option<String>, place: usize, finished: bool) -> PlayerGame {
+        let mut game = PlayerGame { user_id, name, place, finished, moves: 0, move_time_ms: 0, timed_moves: 0, longest_chain: 0 };
+        for (i, m) in history.moves.iter().enumerate().filter(|(_, m)| m.user_id == user_id) {
+            game.moves += 1;
+            game.longest_chain = game.longest_chain.max(jumps(&history.board, &m.path));
+            if let Some(previous) = i.checked_sub(1).and_then(|p| history.moves.get(p)) {
+                let elapsed = m.timestamp.duration_since(previous.timestamp).unwrap_or(Duration::from_secs(0));
+                game.move_time_ms += elapsed.as_millis() as u64;
+                game.timed_moves += 1;
+            }
+        }
+        game
+    }
+}
+
+/// Number of jumps in a move, a single step to a neighbouring point is not a jump.
+fn jumps(board: &Board, path: &[(usize, usize)]) -> usize {
+    match path {
+        [from, to] if board.hex(from.0, from.1).distance(board.hex(to.0, to.1)) == 1 => 0,
+        _ => path.len().saturating_sub(1)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerStats {
+    pub user_id: usize,
+    pub name: Option<String>,
+    pub games: usize,
+    pub wins: usize,
+    /// Number of games per place.
+    pub places: BTreeMap<usize, usize>,
+    pub finished_games: usize,
+    pub moves_to_finish: usize,
+    pub move_time_ms: u64,
+    pub timed_moves: usize,
+    pub longest_chain: usize,
+}
+
+impl PlayerStats {
+    pub fn new(user_id: usize) -> PlayerStats {
+        PlayerStats {
+            user_id,
+            name: None,
+            games: 0,
+            wins: 0,
+            places: BTreeMap::new(),
+            finished_games: 0,
+            moves_to_finish: 0,
+            move_time_ms: 0,
+            timed_moves: 0,
+            longest_chain: 0,
+        }
+    }
+
+    pub fn avg_moves_to_finish(&self) -> Option<f64> {
+        if self.finished_games == 0 {
+            None
+        } else {
+            Some(self.moves_to_finish as f64 / self.finished_games as f64)
+        }
+    }
+
+    pub fn avg_move_time_ms(&self) -> Option<u64> {
+        if self.timed_moves == 0 {
+            None
+        } else {
+            Some(self.move_time_ms / self.timed_moves as u64)
+        }
+    }
+
+    fn add(&mut self, game: &PlayerGame) {
+        if game.name.is_some() {
+            self.name = game.name.clone();
+        }
+        self.games += 1;
+        if game.place == 1 {
+            self.wins += 1;
+        }
+        *self.places.entry(game.place).or_insert(0) += 1;
+        if game.finished {
+            self.finished_games += 1;
+            self.moves_to_finish += game.moves;
+        }
+        self.move_time_ms += game.move_time_ms;
+        self.timed_moves += game.timed_moves;
+        self.longest_chain = self.longest_chain.max(game.longest_chain);
+    }
+}
+
+/// Totals over every completed game of a player, bots are not counted.
+#[derive(Debug, Default)]
+pub struct Stats {
+    players: HashMap<usize, PlayerStats>,
+}
+
+impl Stats {
+    pub fn get(&self, user_id: usize) -> Option<&PlayerStats> {
+        self.players.get(&user_id)
+    }
+
+    pub fn all(&self) -> impl Iterator<Item=&PlayerStats> {
+        self.players.values()
+    }
+
+    pub fn restore(&mut self, stats: Vec<PlayerStats>) {
+        self.players.extend(stats.into_iter().map(|s| (s.user_id, s)));
+    }
+
+    pub fn apply(&mut self, result: &GameResult) {
+        for game in result.players.iter() {
+            self.players.entry(game.user_id)
+                .or_insert_with(|| PlayerStats::new(game.user_id))
+                .add(game);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use crate::game::MoveRecord;
+
+    use super::*;
+
+    fn record(user_id: usize, path: Vec<(usize, usize)>, at_sec: u64) -> MoveRecord {
+        MoveRecord { user_id, color: 0, path, timestamp: SystemTime::UNIX_EPOCH + Duration::from_secs(at_sec) }
+    }
+
+    #[test]
+    fn test_player_game_from_history() {
+        let mut history = GameHistory::default();
+        history.moves.push(record(1, vec![(0, 0), (1, 0)], 0));
+        history.moves.push(record(2, vec![(13, 0), (12, 0)], 5));
+        history.moves.push(record(1, vec![(1, 1), (3, 1), (5, 7)], 7));
+        let game = PlayerGame::from_history(&history, 1, Some("alice".to_string()), 1, true);
+        assert_eq!(2, game.moves);
+        assert_eq!(1, game.timed_moves);
+        assert_eq!(2000, game.move_time_ms);
+        assert_eq!(2, game.longest_chain);
+        assert_eq!(0, jumps(&Board::default(), &[(0, 0), (1, 0)]));
+        assert_eq!(1, jumps(&Board::default(), &[(0, 0), (2, 0)]));
+    }
+
+    #[test]
+    fn test_stats_totals() {
+        let game = |place, finished, moves| PlayerGame { user_id: 1, name: None, place, finished, moves, move_time_ms: 3000, timed_moves: 2, longest_chain: place };
+        let mut stats = Stats::default();
+        for g in [game(1, true, 40), game(2, true, 50), game(3, false, 30)].iter() {
+            stats.apply(&GameResult { room_id: "room".to_string(), winner: None, places: Vec::new(), players: vec![g.clone()], forfeited: Vec::new() });
+        }
+        let s = stats.get(1).unwrap();
+        assert_eq!(3, s.games);
+        assert_eq!(1, s.wins);
+        assert_eq!(Some(&1), s.places.get(&3));
+        assert_eq!(Some(45.0), s.avg_moves_to_finish());
+        assert_eq!(Some(1500), s.avg_move_time_ms());
+        assert_eq!(3, s.longest_chain);
+        assert!(stats.get(2).is_none());
+    }
+}
@@ -0,0 +1,359 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use crate::{AccountList, RatingList, RoomList, StatsList, TournamentList, UserTokens};
+use crate::accounts::Account;
+use crate::bot::{Bot, Difficulty};
+use crate::clock::GameClock;
+use crate::game::{GameHistory, GameState};
+use crate::model::{EventLog, Player, RoomHandle};
+use crate::ratings::Rating;
+use crate::stats::PlayerStats;
+use crate::tournament::Tournament;
+
+pub trait Storage: Send + Sync {
+    fn save(&self, snapshot: &Snapshot) -> io::Result<()>;
+    fn load(&self) -> io::Result<Option<Snapshot>>;
+}
+
+/// Keeps the whole snapshot in a single JSON file, replaced atomically on every save.
+pub struct FileStorage {
+    path: PathBuf,
+}
+
+impl FileStorage {
+    pub fn new(path: impl Into<PathBuf>) -> FileStorage {
+        FileStorage { path: path.into() }
+    }
+}
+
+impl Storage for FileStorage {
+    fn save(&self, snapshot: &Snapshot) -> io::Result<()> {
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(snapshot)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+
+    fn load(&self) -> io::Result<Option<Snapshot>> {
+        match fs::read(&self.path) {
+            Ok(bytes) => Ok(Some(serde_json::from_slice(&bytes)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BotSnapshot {
+    pub difficulty: Difficulty,
+    pub seed: u64,
+    #[serde(default)]
+    pub draws: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlayerSnapshot {
+    pub user_id: usize,
+    pub name: Option<String>,
+    pub ready: bool,
+    pub bot: Option<BotSnapshot>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RoomSnapshot {
+    pub room_id: String,
+    pub name: String,
+    pub winner: Option<usize>,
+    pub created_by: usize,
+    pub game_started: bool,
+    pub game_finished: bool,
+    pub active_player: usize,
+    pub game_state: Option<GameState>,
+    pub players: Vec<PlayerSnapshot>,
+    #[serde(default)]
+    pub history: GameHistory,
+    #[serde(default)]
+    pub last_event_id: u64,
+    #[serde(default)]
+    pub clock: GameClock,
+    #[serde(default)]
+    pub forfeited: Vec<usize>,
+    #[serde(default)]
+    pub walked_out: Vec<usize>,
+    #[serde(default)]
+    pub play_to_end: bool,
+    #[serde(default)]
+    pub standings: Vec<usize>,
+    #[serde(default)]
+    pub private: bool,
+    #[serde(default)]
+    pub reserved: HashMap<usize, usize>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub rooms: Vec<RoomSnapshot>,
+    /// Ids of logged out tokens with their expiry time.
+    #[serde(default)]
+    pub revoked_tokens: Vec<(String, u64)>,
+    pub users_count: usize,
+    #[serde(default)]
+    pub accounts: Vec<Account>,
+    #[serde(default)]
+    pub ratings: Vec<Rating>,
+    #[serde(default)]
+    pub stats: Vec<PlayerStats>,
+    #[serde(default)]
+    pub tournaments: Vec<Tournament>,
+}
+
+impl RoomSnapshot {
+    pub fn from_room(rh: &RoomHandle) -> RoomSnapshot {
+        RoomSnapshot {
+            room_id: rh.room_id.clone(),
+            name: rh.name.clone(),
+            winner: rh.winner,
+            created_by: rh.created_by,
+            game_started: rh.game_started,
+            game_finished: rh.game_finished,
+            active_player: rh.active_player,
+            game_state: rh.game_state.clone(),
+            players: rh.players.iter().map(|p| PlayerSnapshot {
+                user_id: p.user_id,
+                name: p.name.clone(),
+                ready: p.ready,
+                bot: p.bot.as_ref().map(|b| BotSnapshot { difficulty: b.difficulty, seed: b.seed, draws: b.draws }),
+            }).collect(),
+            history: rh.history.clone(),
+            last_event_id: rh.events.last_id,
+            clock: rh.clock.clone(),
+            forfeited: rh.forfeited.clone(),
+            walked_out: rh.walked_out.clone(),
+            play_to_end: rh.play_to_end,
+            standings: rh.standings.clone(),
+            private: rh.private,
+            reserved: rh.reserved.clone(),
+        }
+    }
+
+    /// Results of restored rooms go nowhere until a sender is set.
+    pub fn into_room(self) -> RoomHandle {
+        RoomHandle {
+            room_id: self.room_id,
+            winner: self.winner,
+            created_by: self.created_by,
+            created_time: Instant::now(),
+            last_updated: Instant::now(),
+            name: self.name,
+            game_started: self.game_started,
+            game_finished: self.game_finished,
+            active_player: self.active_player,
+            game_state: self.game_state,
+            players: self.players.into_iter().map(|p| {
+                match p.bot {
+                    Some(b) => Bot::player(p.user_id, p.name.unwrap_or_default(), Bot::resume(b.difficulty, b.seed, b.draws)),
+                    None => {
+                        // The player has to reconnect to the event stream, until then updates go nowhere.
+                        let (sender, _) = mpsc::unbounded_channel();
+                        Player {
+                            user_id: p.user_id,
+                            name: p.name,
+                            sender,
+                            ready: p.ready,
+                            last_active: Instant::now(),
+                            bot: None,
+                        }
+                    }
+                }
+            }).collect(),
+            spectators: Vec::new(),
+            history: self.history,
+            events: EventLog::with_last_id(self.last_event_id),
+            clock: self.clock,
+            forfeited: self.forfeited,
+            walked_out: self.walked_out,
+            results: None,
+            play_to_end: self.play_to_end,
+            standings: self.standings,
+            private: self.private,
+            reserved: self.reserved,
+        }
+    }
+}
+
+impl Snapshot {
+    pub fn capture(rooms: &RoomList, users: &UserTokens, accounts: &AccountList, ratings: &RatingList, stats: &StatsList, users_count: &AtomicUsize) -> Snapshot {
+        Snapshot {
+            rooms: rooms.read().unwrap().values().map(RoomSnapshot::from_room).collect(),
+            revoked_tokens: users.revoked(),
+            users_count: users_count.load(Ordering::Relaxed),
+            accounts: accounts.read().unwrap().values().cloned().collect(),
+            ratings: ratings.read().unwrap().all().cloned().collect(),
+            stats: stats.read().unwrap().all().cloned().collect(),
+            tournaments: Vec::new(),
+        }
+    }
+
+    pub fn with_tournaments(mut self, tournaments: &TournamentList) -> Snapshot {
+        self.tournaments = tournaments.read().unwrap().values().cloned().collect();
+        self
+    }
+
+    /// Returns ids of the rooms with a game in progress, their move timers have to be restarted.
+    pub fn restore(self, rooms: &RoomList, users: &UserTokens, accounts: &AccountList, ratings: &RatingList, stats: &StatsList, users_count: &AtomicUsize) -> Vec<String> {
+        users_count.fetch_max(self.users_count, Ordering::Relaxed);
+        accounts.write().unwrap().extend(self.accounts.into_iter().map(|a| (a.login.clone(), a)));
+        users.restore_revoked(self.revoked_tokens);
+        ratings.write().unwrap().restore(self.ratings);
+        stats.write().unwrap().restore(self.stats);
+        let mut lock = rooms.write().unwrap();
+        let mut in_progress = Vec::new();
+        for room in self.rooms {
+            if room.game_started && !room.game_finished {
+                in_progress.push(room.room_id.clone());
+            }
+            lock.insert(room.room_id.clone(), room.into_room());
+        }
+        info!("Restored {} rooms and {} accounts.", lock.len(), accounts.read().unwrap().len());
+        in_progress
+    }
+}
+
+pub fn save_snapshot(storage: &dyn Storage, snapshot: &Snapshot) {
+    if let Err(e) = storage.save(snapshot) {
+        error!("Could not save snapshot: {:?}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, RwLock};
+
+    use std::time::Duration;
+
+    use crate::{bot, RoomTimersList};
+    use crate::game::{PURPLE, YELLOW};
+    use crate::model::User;
+    use crate::ratings::{GameResult, Ratings};
+    use crate::stats::{PlayerGame, Stats};
+    use crate::sessions::Sessions;
+    use crate::tournament::TournamentFormat;
+
+    use super::*;
+
+    // Alice against a bot, on the bot's turn.
+    fn room_with_a_bot() -> RoomSnapshot {
+        let mut gs = GameState::new();
+        gs.add_cones(1, PURPLE).unwrap();
+        gs.add_cones(2, YELLOW).unwrap();
+        RoomSnapshot {
+            room_id: "room".to_string(),
+            name: "test".to_string(),
+            winner: None,
+            created_by: 1,
+            game_started: true,
+            game_finished: false,
+            active_player: 1,
+            game_state: Some(gs),
+            players: vec![
+                PlayerSnapshot { user_id: 1, name: Some("alice".to_string()), ready: true, bot: None },
+                PlayerSnapshot { user_id: 2, name: Some("bot".to_string()), ready: true, bot: Some(BotSnapshot { difficulty: Difficulty::Hard, seed: 3, draws: 4 }) },
+            ],
+            history: GameHistory::default(),
+            last_event_id: 5,
+            clock: GameClock::default(),
+            forfeited: Vec::new(),
+            walked_out: Vec::new(),
+            play_to_end: false,
+            standings: Vec::new(),
+            private: false,
+            reserved: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_snapshot_roundtrip() {
+        let room = room_with_a_bot();
+        let rooms: RoomList = Arc::new(RwLock::new(HashMap::new()));
+        rooms.write().unwrap().insert("room".to_string(), room.into_room());
+        let users: UserTokens = Arc::new(Sessions::new(b"secret".to_vec(), Duration::from_secs(60)));
+        let (token, claims) = users.issue(&User { user_id: 1, user_name: "alice".to_string(), preferred_color: None });
+        users.revoke(&claims);
+
+        let path = std::env::temp_dir().join(format!("sterligov-snapshot-{}.json", std::process::id()));
+        let storage = FileStorage::new(path.clone());
+        let accounts: AccountList = Arc::new(RwLock::new(HashMap::new()));
+        accounts.write().unwrap().insert("alice".to_string(), Account::new(1, "alice".to_string(), "password", "alice".to_string()));
+        let ratings: RatingList = Arc::new(RwLock::new(Ratings::default()));
+        let game = PlayerGame::from_history(&GameHistory::default(), 2, None, 2, false);
+        let result = GameResult { room_id: "old".to_string(), winner: Some(1), places: vec![(1, 1), (2, 2)], players: vec![game], forfeited: Vec::new() };
+        ratings.write().unwrap().apply(&result);
+        let stats: StatsList = Arc::new(RwLock::new(Stats::default()));
+        stats.write().unwrap().apply(&result);
+        let tournaments: TournamentList = Arc::new(RwLock::new(HashMap::new()));
+        let tournament = Tournament::new("cup".to_string(), "Cup".to_string(), TournamentFormat::Knockout, 1, None);
+        tournaments.write().unwrap().insert("cup".to_string(), tournament);
+        save_snapshot(&storage, &Snapshot::capture(&rooms, &users, &accounts, &ratings, &stats, &AtomicUsize::new(3)).with_tournaments(&tournaments));
+
+        let restored_rooms: RoomList = Arc::new(RwLock::new(HashMap::new()));
+        let restored_users: UserTokens = Arc::new(Sessions::new(b"secret".to_vec(), Duration::from_secs(60)));
+        let users_count = AtomicUsize::new(0);
+        let restored_accounts: AccountList = Arc::new(RwLock::new(HashMap::new()));
+        let restored_ratings: RatingList = Arc::new(RwLock::new(Ratings::default()));
+        let restored_stats: StatsList = Arc::new(RwLock::new(Stats::default()));
+        let snapshot = storage.load().unwrap().unwrap();
+        assert_eq!("Cup", snapshot.tournaments[0].name);
+        let in_progress = snapshot.restore(&restored_rooms, &restored_users, &restored_accounts, &restored_ratings, &restored_stats, &users_count);
+        fs::remove_file(path).unwrap();
+
+        assert_eq!(vec!["room".to_string()], in_progress);
+        assert_eq!(3, users_count.load(Ordering::Relaxed));
+        assert_eq!(None, restored_users.verify(&token));
+        assert!(restored_accounts.read().unwrap()["alice"].verify("password"));
+        assert_eq!(ratings.read().unwrap().get(2).rating, restored_ratings.read().unwrap().get(2).rating);
+        assert_eq!(1, restored_ratings.read().unwrap().get(2).history.len());
+        assert_eq!(1, restored_stats.read().unwrap().get(2).unwrap().games);
+        let lock = restored_rooms.read().unwrap();
+        let r = lock.get("room").unwrap();
+        assert_eq!(1, r.active_player);
+        assert_eq!(5, r.events.last_id);
+        assert_eq!(rooms.read().unwrap()["room"].game_state.as_ref().unwrap().cones, r.game_state.as_ref().unwrap().cones);
+        assert_eq!(Some(Difficulty::Hard), r.players[1].bot.as_ref().map(|b| b.difficulty));
+        assert_eq!(Some(4), r.players[1].bot.as_ref().map(|b| b.draws));
+    }
+
+    #[tokio::test]
+    async fn test_restored_bot_moves() {
+        let snapshot = Snapshot {
+            rooms: vec![room_with_a_bot()],
+            revoked_tokens: Vec::new(),
+            users_count: 2,
+            accounts: Vec::new(),
+            ratings: Vec::new(),
+            stats: Vec::new(),
+            tournaments: Vec::new(),
+        };
+        let snapshot: Snapshot = serde_json::from_slice(&serde_json::to_vec(&snapshot).unwrap()).unwrap();
+        let rooms: RoomList = Arc::new(RwLock::new(HashMap::new()));
+        let rooms_timers: RoomTimersList = Arc::new(RwLock::new(HashMap::new()));
+        let users: UserTokens = Arc::new(Sessions::new(b"secret".to_vec(), Duration::from_secs(60)));
+        let in_progress = snapshot.restore(&rooms, &users, &Arc::new(RwLock::new(HashMap::new())),
+                                           &Arc::new(RwLock::new(Ratings::default())), &Arc::new(RwLock::new(Stats::default())), &AtomicUsize::new(0));
+
+        bot::resume_bots(rooms.clone(), rooms_timers, &in_progress);
+        tokio::time::delay_for(Duration::from_millis(1500)).await;
+        let lock = rooms.read().unwrap();
+        let r = lock.get("room").unwrap();
+        assert_eq!(1, r.history.moves.len());
+        assert_eq!(2, r.history.moves[0].user_id);
+        assert_eq!(0, r.active_player);
+    }
+}
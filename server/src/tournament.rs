@@ -0,0 +1,440 @@
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::game::{PURPLE, YELLOW};
+
+/// Colors of a tournament game, the first player of a pairing plays purple.
+pub const TOURNAMENT_COLORS: [usize; 2] = [PURPLE, YELLOW];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentFormat {
+    RoundRobin,
+    Swiss,
+    Knockout,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum TournamentStatus {
+    Registration,
+    InProgress,
+    Finished,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entrant {
+    pub user_id: usize,
+    pub name: String,
+    /// 1 is the strongest, set by rating when the tournament starts.
+    pub seed: usize,
+}
+
+/// A two player game of a round. A pairing with a single player is a bye, which counts as a win.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Pairing {
+    pub players: Vec<usize>,
+    pub room_id: Option<String>,
+    pub winner: Option<usize>,
+    pub finished: bool,
+}
+
+impl Pairing {
+    fn new(players: Vec<usize>) -> Pairing {
+        let bye = players.len() == 1;
+        Pairing {
+            winner: if bye { players.first().cloned() } else { None },
+            finished: bye,
+            players,
+            room_id: None,
+        }
+    }
+
+    fn points(&self, user_id: usize) -> f64 {
+        match self.winner {
+            Some(w) if w == user_id => 1.0,
+            Some(_) => 0.0,
+            None => 0.5
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TournamentStanding {
+    pub place: usize,
+    pub user_id: usize,
+    pub name: String,
+    pub points: f64,
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+    /// Sum of the opponents' points (Buchholz), used to split ties.
+    pub tiebreak: f64,
+    /// Knockout only, the player is still in the bracket.
+    pub alive: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Tournament {
+    pub id: String,
+    pub name: String,
+    pub format: TournamentFormat,
+    pub created_by: usize,
+    pub status: TournamentStatus,
+    pub entrants: Vec<Entrant>,
+    pub rounds: Vec<Vec<Pairing>>,
+    pub total_rounds: usize,
+}
+
+impl Tournament {
+    /// `rounds` is only used by Swiss tournaments, by default there are enough rounds for a single winner.
+    pub fn new(id: String, name: String, format: TournamentFormat, created_by: usize, rounds: Option<usize>) -> Tournament {
+        Tournament {
+            id,
+            name,
+            format,
+            created_by,
+            status: TournamentStatus::Registration,
+            entrants: Vec::new(),
+            rounds: Vec::new(),
+            total_rounds: rounds.unwrap_or(0),
+        }
+    }
+
+    pub fn register(&mut self, user_id: usize, name: String) -> bool {
+        if self.status != TournamentStatus::Registration || self.entrants.iter().any(|e| e.user_id == user_id) {
+            return false;
+        }
+        let seed = self.entrants.len() + 1;
+        self.entrants.push(Entrant { user_id, name, seed });
+        true
+    }
+
+    /// Seeds the players by rating, registration order breaks ties, and pairs the first round.
+    pub fn start(&mut self, rating: impl Fn(usize) -> f64) -> bool {
+        if self.status != TournamentStatus::Registration || self.entrants.len() < 2 {
+            return false;
+        }
+        self.entrants.sort_by(|a, b| rating(b.user_id).total_cmp(&rating(a.user_id)).then(a.seed.cmp(&b.seed)));
+        for (i, e) in self.entrants.iter_mut().enumerate() {
+            e.seed = i + 1;
+        }
+        let n = self.entrants.len();
+        self.total_rounds = match self.format {
+            TournamentFormat::RoundRobin => if n % 2 == 0 { n - 1 } else { n },
+            TournamentFormat::Knockout => log2_ceil(n),
+            TournamentFormat::Swiss if self.total_rounds == 0 => log2_ceil(n),
+            TournamentFormat::Swiss => self.total_rounds.min(n - 1),
+        };
+        self.status = TournamentStatus::InProgress;
+        self.next_round();
+        true
+    }
+
+    /// Records the outcome of the game played in the room, a game without a winner is a draw
+    /// unless some of the players forfeited or walked out.
+    /// Returns true when the game belongs to the tournament.
+    pub fn record(&mut self, room_id: &str, winner: Option<usize>, forfeited: &[usize]) -> bool {
+        let position = self.rounds.iter().enumerate()
+            .flat_map(|(r, round)| round.iter().enumerate().map(move |(i, p)| (r, i, p)))
+            .find(|(_, _, p)| !p.finished && p.room_id.as_deref() == Some(room_id))
+            .map(|(r, i, _)| (r, i));
+        let (r, i) = match position {
+            Some(position) => position,
+            None => return false
+        };
+        let players = &self.rounds[r][i].players;
+        let mut winner = winner.filter(|w| players.contains(w));
+        // A knockout game has to send somebody through: the players who stayed go before those who left,
+        // then the higher seed.
+        if self.format == TournamentFormat::Knockout && winner.is_none() {
+            winner = players.iter().min_by_key(|id| (forfeited.contains(id), self.seed(**id))).cloned();
+        }
+        let pairing = &mut self.rounds[r][i];
+        pairing.winner = winner;
+        pairing.finished = true;
+        if self.rounds.last().is_some_and(|r| r.iter().all(|p| p.finished)) {
+            self.next_round();
+        }
+        true
+    }
+
+    /// Games of the current round waiting for a room, as (index in the round, players).
+    pub fn unseated(&self) -> Vec<(usize, Vec<usize>)> {
+        self.rounds.last().map(|r| r.iter().enumerate()
+            .filter(|(_, p)| !p.finished && p.room_id.is_none())
+            .map(|(i, p)| (i, p.players.clone()))
+            .collect()
+        ).unwrap_or_default()
+    }
+
+    pub fn seat(&mut self, index: usize, room_id: String) {
+        if let Some(p) = self.rounds.last_mut().and_then(|r| r.get_mut(index)) {
+            p.room_id = Some(room_id);
+        }
+    }
+
+    pub fn standings(&self) -> Vec<TournamentStanding> {
+        let mut standings: Vec<TournamentStanding> = self.entrants.iter().map(|e| {
+            let games: Vec<&Pairing> = self.games_of(e.user_id).collect();
+            TournamentStanding {
+                place: 0,
+                user_id: e.user_id,
+                name: e.name.clone(),
+                points: games.iter().map(|p| p.points(e.user_id)).fold(0.0, |a, b| a + b),
+                wins: games.iter().filter(|p| p.winner == Some(e.user_id)).count(),
+                draws: games.iter().filter(|p| p.winner.is_none()).count(),
+                losses: games.iter().filter(|p| p.winner.is_some_and(|w| w != e.user_id)).count(),
+                tiebreak: games.iter()
+                    .flat_map(|p| p.players.iter().filter(|id| **id != e.user_id))
+                    .map(|id| self.points(*id))
+                    .fold(0.0, |a, b| a + b),
+                alive: self.format == TournamentFormat::Knockout && self.is_alive(e.user_id),
+            }
+        }).collect();
+        standings.sort_by(|a, b| {
+            b.alive.cmp(&a.alive)
+                .then(b.points.total_cmp(&a.points))
+                .then(b.tiebreak.total_cmp(&a.tiebreak))
+                .then(self.seed(a.user_id).cmp(&self.seed(b.user_id)))
+        });
+        for (i, s) in standings.iter_mut().enumerate() {
+            s.place = i + 1;
+        }
+        standings
+    }
+
+    fn next_round(&mut self) {
+        if self.rounds.len() >= self.total_rounds || (self.format == TournamentFormat::Knockout && self.alive_players().len() < 2) {
+            self.status = TournamentStatus::Finished;
+            return;
+        }
+        let pairings = match self.format {
+            TournamentFormat::RoundRobin => self.round_robin_pairings(),
+            TournamentFormat::Swiss => self.swiss_pairings(),
+            TournamentFormat::Knockout => self.knockout_pairings(),
+        };
+        self.rounds.push(pairings.into_iter().map(Pairing::new).collect());
+        // A round of byes only is over at once.
+        if self.rounds.last().is_some_and(|r| r.iter().all(|p| p.finished)) {
+            self.next_round();
+        }
+    }
+
+    fn games_of(&self, user_id: usize) -> impl Iterator<Item=&Pairing> {
+        self.rounds.iter().flatten().filter(move |p| p.finished && p.players.contains(&user_id))
+    }
+
+    fn points(&self, user_id: usize) -> f64 {
+        self.games_of(user_id).map(|p| p.points(user_id)).fold(0.0, |a, b| a + b)
+    }
+
+    fn seed(&self, user_id: usize) -> usize {
+        self.entrants.iter().find(|e| e.user_id == user_id).map(|e| e.seed).unwrap_or(usize::MAX)
+    }
+
+    fn is_alive(&self, user_id: usize) -> bool {
+        !self.rounds.iter().flatten().any(|p| p.finished && p.players.contains(&user_id) && p.winner != Some(user_id))
+    }
+
+    fn alive_players(&self) -> Vec<usize> {
+        self.entrants.iter().map(|e| e.user_id).filter(|id| self.is_alive(*id)).collect()
+    }
+
+    // Circle method: the first player stays, the others rotate by one every round.
+    fn round_robin_pairings(&self) -> Vec<Vec<usize>> {
+        let mut players: Vec<Option<usize>> = self.entrants.iter().map(|e| Some(e.user_id)).collect();
+        if players.len() % 2 == 1 {
+            players.push(None);
+        }
+        let n = players.len();
+        let round = self.rounds.len();
+        players[1..].rotate_right(round % (n - 1));
+        (0..n / 2).map(|i| {
+            // Alternate colors of the fixed player between rounds.
+            let (a, b) = if i == 0 && round % 2 == 1 { (players[n - 1], players[0]) } else { (players[i], players[n - 1 - i]) };
+            a.into_iter().chain(b).collect()
+        }).collect()
+    }
+
+    // Players with the same score meet, the leader plays the next one they have not met yet.
+    // With an odd number of players the lowest one without a bye sits out.
+    fn swiss_pairings(&self) -> Vec<Vec<usize>> {
+        let mut order: Vec<usize> = self.entrants.iter().map(|e| e.user_id).collect();
+        order.sort_by(|a, b| self.points(*b).total_cmp(&self.points(*a)).then(self.seed(*a).cmp(&self.seed(*b))));
+        let bye = if order.len() % 2 == 1 {
+            let had_bye: HashSet<usize> = self.rounds.iter().flatten().filter(|p| p.players.len() == 1).map(|p| p.players[0]).collect();
+            let bye = order.iter().rposition(|id| !had_bye.contains(id)).unwrap_or(order.len() - 1);
+            Some(order.remove(bye))
+        } else {
+            None
+        };
+        // When every pairing has a rematch the players are paired in order of the standings.
+        let mut pairings = self.pair_without_rematches(&order)
+            .unwrap_or_else(|| order.chunks(2).map(|c| c.to_vec()).collect());
+        pairings.extend(bye.map(|id| vec![id]));
+        pairings
+    }
+
+    /// Pairs the first player with the closest one in `order` they have not played yet, backtracking
+    /// when the players left could not be paired.
+    fn pair_without_rematches(&self, order: &[usize]) -> Option<Vec<Vec<usize>>> {
+        let (first, rest) = match order.split_first() {
+            Some(split) => split,
+            None => return Some(Vec::new())
+        };
+        for (i, opponent) in rest.iter().enumerate().filter(|(_, id)| !self.have_met(*first, **id)) {
+            let others: Vec<usize> = rest.iter().enumerate().filter(|(j, _)| *j != i).map(|(_, id)| *id).collect();
+            if let Some(mut pairings) = self.pair_without_rematches(&others) {
+                pairings.insert(0, vec![*first, *opponent]);
+                return Some(pairings);
+            }
+        }
+        None
+    }
+
+    fn have_met(&self, a: usize, b: usize) -> bool {
+        self.rounds.iter().flatten().any(|p| p.players.contains(&a) && p.players.contains(&b))
+    }
+
+    // The first round pairs seed 1 with the last seed of a full bracket, the missing seeds are byes.
+    // Later rounds pair the winners of neighbouring games.
+    fn knockout_pairings(&self) -> Vec<Vec<usize>> {
+        match self.rounds.last() {
+            None => {
+                let size = 1 << log2_ceil(self.entrants.len());
+                bracket_order(size).chunks(2).map(|pair| {
+                    pair.iter().filter_map(|seed| self.entrants.get(seed - 1).map(|e| e.user_id)).collect()
+                }).collect()
+            }
+            Some(round) => round.chunks(2).map(|pair| pair.iter().filter_map(|p| p.winner).collect()).collect()
+        }
+    }
+}
+
+/// Seeds in bracket order, so that the top seeds only meet in the last rounds: 1, 8, 4, 5, 2, 7, 3, 6.
+fn bracket_order(size: usize) -> Vec<usize> {
+    let mut order = vec![1];
+    while order.len() < size {
+        let n = order.len() * 2;
+        order = order.iter().flat_map(|s| vec![*s, n + 1 - s]).collect();
+    }
+    order
+}
+
+fn log2_ceil(n: usize) -> usize {
+    let mut rounds = 0;
+    while (1 << rounds) < n {
+        rounds += 1;
+    }
+    rounds
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tournament(format: TournamentFormat, players: usize) -> Tournament {
+        let mut t = Tournament::new("t".to_string(), "test".to_string(), format, 1, None);
+        for id in 1..=players {
+            assert!(t.register(id, format!("player{}", id)));
+        }
+        assert!(!t.register(1, "again".to_string()));
+        // Lower ids have higher ratings.
+        assert!(t.start(|id| 2000.0 - id as f64));
+        t
+    }
+
+    // Seats every game of the current round and lets the lower id win.
+    fn play_round(t: &mut Tournament) {
+        let round = t.rounds.len();
+        for (i, _) in t.unseated() {
+            t.seat(i, format!("{}-{}", round, i));
+        }
+        let games: Vec<(String, Option<usize>)> = t.rounds.last().unwrap().iter()
+            .filter(|p| !p.finished)
+            .map(|p| (p.room_id.clone().unwrap(), p.players.iter().min().cloned()))
+            .collect();
+        for (room_id, winner) in games {
+            assert!(t.record(&room_id, winner, &[]));
+        }
+    }
+
+    #[test]
+    fn test_round_robin_everybody_meets_once() {
+        let mut t = tournament(TournamentFormat::RoundRobin, 5);
+        assert_eq!(5, t.total_rounds);
+        while t.status == TournamentStatus::InProgress {
+            play_round(&mut t);
+        }
+        for a in 1..=5 {
+            for b in (a + 1)..=5 {
+                let met = t.rounds.iter().flatten().filter(|p| p.players.contains(&a) && p.players.contains(&b)).count();
+                assert_eq!(1, met, "{} and {}", a, b);
+            }
+        }
+        let standings = t.standings();
+        assert_eq!(vec![1, 2, 3, 4, 5], standings.iter().map(|s| s.user_id).collect::<Vec<usize>>());
+        // Four games and a bye.
+        assert_eq!(5.0, standings[0].points);
+        assert_eq!(1, standings[4].wins);
+    }
+
+    #[test]
+    fn test_swiss_avoids_rematches() {
+        let mut t = tournament(TournamentFormat::Swiss, 6);
+        assert_eq!(3, t.total_rounds);
+        while t.status == TournamentStatus::InProgress {
+            play_round(&mut t);
+        }
+        assert_eq!(3, t.rounds.len());
+        let pairs: Vec<Vec<usize>> = t.rounds.iter().flatten().map(|p| p.players.clone()).collect();
+        for (i, p) in pairs.iter().enumerate() {
+            assert!(!pairs[i + 1..].contains(p), "rematch {:?}", p);
+        }
+        assert_eq!(1, t.standings()[0].user_id);
+        assert_eq!(3.0, t.standings()[0].points);
+    }
+
+    #[test]
+    fn test_knockout_with_byes() {
+        let mut t = tournament(TournamentFormat::Knockout, 6);
+        assert_eq!(3, t.total_rounds);
+        let first: Vec<Vec<usize>> = t.rounds[0].iter().map(|p| p.players.clone()).collect();
+        assert_eq!(vec![vec![1], vec![4, 5], vec![2], vec![3, 6]], first);
+        play_round(&mut t);
+        assert_eq!(vec![vec![1, 4], vec![2, 3]], t.rounds[1].iter().map(|p| p.players.clone()).collect::<Vec<_>>());
+        t.seat(0, "semi1".to_string());
+        t.seat(1, "semi2".to_string());
+        // An upset, then a draw sends the higher seed through.
+        assert!(t.record("semi1", Some(4), &[]));
+        assert!(t.record("semi2", None, &[]));
+        assert!(!t.record("semi2", Some(3), &[]));
+        assert_eq!(vec![vec![4, 2]], t.rounds[2].iter().map(|p| p.players.clone()).collect::<Vec<_>>());
+        // The higher seed goes on even when it is not listed first.
+        t.seat(0, "final".to_string());
+        assert!(t.record("final", None, &[]));
+        assert_eq!(TournamentStatus::Finished, t.status);
+        let standings = t.standings();
+        assert_eq!(2, standings[0].user_id);
+        assert!(standings[0].alive);
+        assert_eq!(4, standings[1].user_id);
+    }
+
+    #[test]
+    fn test_knockout_walk_out() {
+        let mut t = tournament(TournamentFormat::Knockout, 2);
+        t.seat(0, "final".to_string());
+        // The higher seed left the room, the game was dropped without a winner.
+        assert!(t.record("final", None, &[1]));
+        assert_eq!(TournamentStatus::Finished, t.status);
+        assert_eq!(Some(2), t.rounds[0][0].winner);
+    }
+
+    #[test]
+    fn test_bracket_order() {
+        assert_eq!(vec![1, 8, 4, 5, 2, 7, 3, 6], bracket_order(8));
+        assert_eq!(vec![1, 2], bracket_order(2));
+    }
+}
@@ -1,14 +1,16 @@
-use futures::{Stream, StreamExt};
+use futures::{SinkExt, Stream, StreamExt};
 use log::{error, info};
 use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
 use warp::Error;
 use warp::filters::sse::ServerSentEvent;
+use warp::ws::WebSocket;
 
-use crate::{Result, RoomHandle, User};
-use crate::model::{Message, Player, RoomFull};
-use tokio::sync::mpsc::UnboundedReceiver;
-use std::time::Instant;
+use crate::{handler, MatchQueue, Result, RoomHandle, RoomList, RoomTimersList, User};
+use crate::model::{Message, Player, PublishToARoomRequest, RoomFull, RoomStateUpdate, Spectator, UpdateRoomStateRequest};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use std::cmp::max;
+use std::time::{Duration, Instant};
 use core::fmt::Debug;
 
 #[derive(Serialize, Debug)]
@@ -38,6 +40,29 @@ impl ChatMessage {
     }
 }
 
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SocketRequest {
+    Move(PublishToARoomRequest),
+    Chat(SendMessageRequest),
+    Update(UpdateRoomStateRequest),
+}
+
+#[derive(Serialize, Debug)]
+pub struct SocketError {
+    name: String,
+    message: String,
+}
+
+impl SocketError {
+    fn new(message: String) -> SocketError {
+        SocketError {
+            name: "error".to_string(),
+            message,
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct PlayerJoinedUpdate {
     name: String,
@@ -49,6 +74,21 @@ pub struct PlayerJoinedUpdate {
     player_ready: bool
 }
 
+#[derive(Serialize, Debug)]
+pub struct ResyncRequiredUpdate {
+    name: String,
+    last_event_id: u64,
+}
+
+impl ResyncRequiredUpdate {
+    fn new(last_event_id: u64) -> ResyncRequiredUpdate {
+        ResyncRequiredUpdate {
+            name: "resync_required".to_string(),
+            last_event_id,
+        }
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct PlayerLeftUpdate {
     name: String,
@@ -60,7 +100,7 @@ pub struct PlayerLeftUpdate {
 }
 
 impl PlayerJoinedUpdate {
-    fn new(user_id: usize,
+    pub(crate) fn new(user_id: usize,
            room_id: String,
            player_cones: Vec<(usize, usize)>,
            player_name: String,
@@ -95,15 +135,71 @@ impl PlayerLeftUpdate {
     }
 }
 
-pub fn client_connection(room_id: String, user: User, room: &mut RoomHandle) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent + Send + 'static, Error>> + Send + 'static> {
+type PlayerSender = UnboundedSender<std::result::Result<Message, Error>>;
+type PlayerReceiver = UnboundedReceiver<std::result::Result<Message, Error>>;
+
+pub fn client_connection(room_id: String, user: User, room: &mut RoomHandle, last_event_id: Option<u64>) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent, Error>> + Send + 'static> {
+    let (_, player_receiver) = join_room(room_id, user, room, last_event_id)?;
+    wrap(player_receiver)
+}
+
+pub fn spectator_connection(user: User, room: &mut RoomHandle, last_event_id: Option<u64>) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent, Error>> + Send + 'static> {
+    let (_, receiver) = watch_room(user, room, last_event_id);
+    wrap(receiver)
+}
+
+pub fn queue_connection(user: User, queue: &MatchQueue) -> Result<impl Stream<Item=std::result::Result<impl ServerSentEvent, Error>> + Send + 'static> {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    queue.write().unwrap().connect(user.user_id, sender);
+    info!("User with id {} waits for a match", user.user_id);
+    wrap(receiver)
+}
+
+pub fn watch_room(user: User, room: &mut RoomHandle, last_event_id: Option<u64>) -> (PlayerSender, PlayerReceiver) {
+    let (sender, receiver) = mpsc::unbounded_channel();
+    if let Some(s) = room.spectators.iter_mut().find(|s| s.user_id == user.user_id) {
+        s.sender = sender.clone();
+        s.last_active = Instant::now();
+    } else {
+        room.spectators.push(Spectator {
+            user_id: user.user_id,
+            sender: sender.clone(),
+            last_active: Instant::now(),
+        });
+    }
+    info!("User with id {} watches room {}", user.user_id, room.room_id);
+    replay_events(&sender, room, last_event_id);
+    let update = RoomStateUpdate::new(room);
+    send_update(room, &update);
+    (sender, receiver)
+}
+
+fn replay_events(sender: &PlayerSender, room: &RoomHandle, last_event_id: Option<u64>) {
+    if let Some(last_id) = last_event_id {
+        if room.events.first_id().is_some_and(|first| first > last_id + 1) {
+            send_to(sender, &ResyncRequiredUpdate::new(last_id));
+        }
+        for (id, text) in room.events.since(last_id) {
+            if sender.send(Ok(Message::Sequenced(*id, text.clone()))).is_err() {
+                error!("Could not replay event {} in room {}", id, room.room_id);
+            }
+        }
+    }
+}
+
+pub fn join_room(room_id: String, user: User, room: &mut RoomHandle, last_event_id: Option<u64>) -> Result<(PlayerSender, PlayerReceiver)> {
     if room.players.len() > 5 {
         error!("Room full");
         Err(warp::reject::custom(RoomFull))
     } else if room.game_started && room.game_state.as_ref().filter(|gs| { gs.players_colors.get(&user.user_id).is_some() }).is_none() {
         error!("Game is already started.");
         Err(warp::reject::custom(RoomFull))
+    } else if room.private && !room.reserved.contains_key(&user.user_id) {
+        error!("User {} has no seat in room {}.", user.user_id, room_id);
+        Err(warp::reject::custom(RoomFull))
     } else {
         let color = room.game_state.as_ref().map(|gs| { gs.players_colors.get(&user.user_id).cloned() }).flatten();
+        let reserved_color = room.reserved.get(&user.user_id).cloned();
         let default_color = room.game_state.as_ref().map(|gs| {
             let mut c = 1;
             while c < 7 {
@@ -119,7 +215,10 @@ pub fn client_connection(room_id: String, user: User, room: &mut RoomHandle) ->
             }
             c
         }).or(Some(*&room.players.len() + 1)).unwrap();
-        let player_color = color.unwrap_or_else(|| default_color);
+        let preferred_color = user.preferred_color.filter(|c| {
+            *c > 0 && *c < 7 && room.game_state.as_ref().is_some_and(|gs| !gs.players_colors.values().any(|v| v == c))
+        });
+        let player_color = color.or(reserved_color).or(preferred_color).unwrap_or(default_color);
         let mut update = PlayerJoinedUpdate::new(
             user.user_id,
             room_id.to_string(),
@@ -129,25 +228,25 @@ pub fn client_connection(room_id: String, user: User, room: &mut RoomHandle) ->
             false
         );
         let (player_sender, player_receiver) = mpsc::unbounded_channel();
-        let result = if let Some(p) = room.players.iter_mut().find(|p| p.user_id == user.user_id) {
+        room.spectators.retain(|s| s.user_id != user.user_id);
+        room.walked_out.retain(|id| *id != user.user_id);
+        if let Some(p) = room.players.iter_mut().find(|p| p.user_id == user.user_id) {
             p.sender = player_sender.clone();
             update.player_ready = p.ready;
-            wrap(player_receiver)
         } else {
-            let result= wrap(player_receiver);
             let player = Player {
                 sender: player_sender.clone(),
                 user_id: user.user_id,
                 name: Some(user.user_name.clone()),
                 last_active: Instant::now(),
-                ready: false
+                ready: false,
+                bot: None
             };
             room.players.push(player);
             if room.players.len() == 1 {
                 room.created_by = user.user_id.clone();
             }
-            result
-        };
+        }
         info!("User with id {} connected to room {}", user.user_id, room_id);
         if let Some(gs) = room.game_state.as_mut() {
             if color.is_none() {
@@ -157,8 +256,123 @@ pub fn client_connection(room_id: String, user: User, room: &mut RoomHandle) ->
             }
             update.player_cones = gs.get_cones(&user.user_id);
         }
-        send_update(&room, &update);
-        result
+        replay_events(&player_sender, room, last_event_id);
+        send_update(room, &update);
+        Ok((player_sender, player_receiver))
+    }
+}
+
+/// Drops the players and spectators who did not answer for `player_ttl`. Until the game starts
+/// the colors and cones of the players who left are freed, except the seats reserved in the room.
+pub fn remove_stale_players(room: &mut RoomHandle, player_ttl: Duration) {
+    for p in room.players.iter_mut() {
+        if p.bot.is_some() || p.sender.send(Ok(Message::event("test".to_string()))).is_ok() {
+            p.last_active = Instant::now();
+        }
+    }
+    let seated: Vec<usize> = room.players.iter().map(|p| p.user_id).collect();
+    room.players.retain(|p| Instant::now() - p.last_active < player_ttl);
+    // Leaving a running game counts against the player only when somebody else is still there.
+    if room.game_started && !room.game_finished && !room.players.is_empty() {
+        for id in seated {
+            if !room.players.iter().any(|p| p.user_id == id) && !room.walked_out.contains(&id) {
+                room.walked_out.push(id);
+            }
+        }
+    }
+    for s in room.spectators.iter_mut() {
+        if s.sender.send(Ok(Message::event("test".to_string()))).is_ok() {
+            s.last_active = Instant::now();
+        }
+    }
+    room.spectators.retain(|s| Instant::now() - s.last_active < player_ttl);
+    room.active_player %= max(room.players.len(), 1);
+    let players = &room.players;
+    let reserved = &room.reserved;
+    let game_started = room.game_started;
+    let mut removed_players = Vec::new();
+    if let Some(gs) = room.game_state.as_mut() {
+        gs.players_colors.retain(|id, c| {
+            let valid = players.iter().any(|p| p.user_id == *id) || reserved.contains_key(id);
+            if !valid {
+                removed_players.push((*id, *c));
+            }
+            valid
+        });
+        if !game_started {
+            gs.cones.retain(|_, id| !removed_players.iter().any(|(removed, _)| removed == id));
+        }
+    }
+    for (user_id, player_color) in removed_players {
+        let update = PlayerLeftUpdate::new(user_id, room.room_id.clone(), room.active_player, !game_started, player_color);
+        send_update(room, &update);
+    }
+}
+
+pub async fn socket_connection(socket: WebSocket, player_sender: PlayerSender, mut player_receiver: PlayerReceiver, room_id: String, user: User, rooms: RoomList, rooms_timers: RoomTimersList) {
+    let (mut socket_sender, mut socket_receiver) = socket.split();
+    tokio::spawn(async move {
+        while let Some(Ok(msg)) = player_receiver.next().await {
+            let socket_msg = match msg {
+                Message::Text(text) | Message::Sequenced(_, text) => warp::ws::Message::text(text),
+                Message::Event(_) => warp::ws::Message::ping(Vec::new()),
+            };
+            if let Err(e) = socket_sender.send(socket_msg).await {
+                info!("WebSocket closed: {:?}", e);
+                break;
+            }
+        }
+    });
+    while let Some(result) = socket_receiver.next().await {
+        let msg = match result {
+            Ok(msg) => msg,
+            Err(e) => {
+                error!("WebSocket error for user {}: {:?}", user.user_id, e);
+                break;
+            }
+        };
+        if msg.is_close() {
+            break;
+        }
+        if let Ok(text) = msg.to_str() {
+            dispatch(text, &player_sender, &room_id, &user, &rooms, &rooms_timers).await;
+        }
+    }
+    info!("User {} disconnected from the WebSocket of room {}", user.user_id, room_id);
+}
+
+// Runs a request sent over the socket, errors go back to the sender only.
+async fn dispatch(text: &str, player_sender: &PlayerSender, room_id: &str, user: &User, rooms: &RoomList, rooms_timers: &RoomTimersList) {
+    let outcome = match serde_json::from_str::<SocketRequest>(text) {
+        Ok(SocketRequest::Move(request)) => {
+            handler::make_a_move(room_id.to_string(), user.user_id, rooms_timers.clone(), rooms.clone(), request).map(|_| ())
+        }
+        Ok(SocketRequest::Chat(request)) => handler::send_chat_message(room_id, user, request, rooms),
+        Ok(SocketRequest::Update(request)) => {
+            handler::update_room_state(room_id.to_string(), user.user_id, rooms.clone(), request, rooms_timers.clone()).await;
+            Ok(())
+        }
+        Err(e) => {
+            error!("Could not parse WebSocket message {}: {:?}", text, e);
+            send_to(player_sender, &SocketError::new(format!("Invalid message: {}", e)));
+            return;
+        }
+    };
+    if let Err(e) = outcome {
+        send_to(player_sender, &SocketError::new(format!("Request failed: {:?}", e)));
+    }
+}
+
+fn send_to(sender: &PlayerSender, upd: &(impl Serialize + Debug)) {
+    match serde_json::ser::to_string(upd) {
+        Ok(str) => {
+            if let Err(e) = sender.send(Ok(Message::Text(str))) {
+                error!("Error while sending update to player. {:?}, {:?}", upd, e);
+            }
+        }
+        Err(msg) => {
+            error!("Error while serializing update {:?}, {:?}", upd, msg);
+        }
     }
 }
 
@@ -168,7 +382,11 @@ fn wrap(player_receiver: UnboundedReceiver<std::result::Result<Message, Error>>)
             info!("Message: {:?}", msg);
             match msg {
                 Message::Text(text) => {
-                    warp::sse::data(text).into_a()
+                    warp::sse::data(text).into_a().into_a()
+                }
+
+                Message::Sequenced(id, text) => {
+                    (warp::sse::id(id), warp::sse::data(text)).into_b().into_a()
                 }
 
                 Message::Event(event) => {
@@ -179,19 +397,245 @@ fn wrap(player_receiver: UnboundedReceiver<std::result::Result<Message, Error>>)
     }))
 }
 
-pub fn send_update(rh: &RoomHandle, upd: &(impl Serialize + Debug)) {
-    for p in &rh.players {
-        match serde_json::ser::to_string(upd) {
-            Ok(str) => {
-                if let Err(e) = p.sender.send(Ok(Message::Text(str))) {
+// Updates are numbered and kept in the room's event log, so that reconnecting clients can catch up.
+pub fn send_update(rh: &mut RoomHandle, upd: &(impl Serialize + Debug)) {
+    match serde_json::ser::to_string(upd) {
+        Ok(str) => {
+            let id = rh.events.push(str.clone());
+            for sender in listeners(rh) {
+                if let Err(e) = sender.send(Ok(Message::Sequenced(id, str.clone()))) {
                     error!("Error while sending update  to players. {:?}, {:?}" , upd, e);
-
                 }
             }
-            Err(msg) => {
-                error!("Error while serializing update {:?}, {:?}" , upd, msg);
+        }
+        Err(msg) => {
+            error!("Error while serializing update {:?}, {:?}" , upd, msg);
+        }
+    }
+}
+
+// For frequent updates that are useless after a reconnect, like the move timer.
+pub fn send_transient_update(rh: &RoomHandle, upd: &(impl Serialize + Debug)) {
+    for sender in listeners(rh) {
+        send_to(sender, upd);
+    }
+}
+
+fn listeners(rh: &RoomHandle) -> impl Iterator<Item=&PlayerSender> {
+    rh.players.iter().filter(|p| p.bot.is_none()).map(|p| &p.sender)
+        .chain(rh.spectators.iter().map(|s| &s.sender))
+}
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::game::{GameHistory, GameState, GREEN, PURPLE, YELLOW};
+    use crate::clock::GameClock;
+    use crate::model::{EventLog, RoomDesc};
+
+    use super::*;
+
+    fn room() -> RoomHandle {
+        RoomHandle {
+            room_id: "room".to_string(),
+            winner: None,
+            created_by: 1,
+            created_time: Instant::now(),
+            last_updated: Instant::now(),
+            name: "test".to_string(),
+            game_started: false,
+            game_finished: false,
+            active_player: 0,
+            game_state: Some(GameState::new()),
+            players: Vec::new(),
+            spectators: Vec::new(),
+            history: GameHistory::default(),
+            events: EventLog::default(),
+            clock: GameClock::default(),
+            forfeited: Vec::new(),
+            walked_out: Vec::new(),
+            results: None,
+            play_to_end: false,
+            standings: Vec::new(),
+            private: false,
+            reserved: HashMap::new(),
+        }
+    }
+
+    fn user(user_id: usize) -> User {
+        User { user_id, user_name: format!("user{}", user_id), preferred_color: None }
+    }
+
+    fn texts(receiver: &mut PlayerReceiver) -> Vec<String> {
+        let mut result = Vec::new();
+        while let Ok(Ok(Message::Sequenced(_, text))) = receiver.try_recv() {
+            result.push(text);
+        }
+        result
+    }
+
+    #[test]
+    fn test_spectator_does_not_take_a_seat() {
+        let mut r = room();
+        let (_, mut player) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+        let (_, mut spectator) = watch_room(user(2), &mut r, Some(0));
+        r.game_started = true;
+
+        assert_eq!(1, r.players.len());
+        assert_eq!(1, r.game_state.as_ref().unwrap().players_colors.len());
+        assert_eq!(1, RoomDesc::from_room(&r).spectators);
+        assert!(texts(&mut spectator)[0].contains("player_joined"));
+        assert!(texts(&mut player).last().unwrap().contains("room_state_update"));
+
+        send_update(&mut r, &ChatMessage::new("user1", 1, Some("hi".to_string()), None));
+        assert!(texts(&mut spectator)[0].contains("chat_message"));
+        assert!(join_room("room".to_string(), user(3), &mut r, None).is_err());
+    }
+
+    fn errors(receiver: &mut PlayerReceiver) -> Vec<String> {
+        let mut result = Vec::new();
+        while let Ok(Ok(msg)) = receiver.try_recv() {
+            if let Message::Text(text) = msg {
+                result.push(text);
             }
         }
+        result
+    }
+
+    fn socket_room() -> (RoomList, RoomTimersList, PlayerReceiver) {
+        let mut r = room();
+        let (_, receiver) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+        let rooms: RoomList = Default::default();
+        rooms.write().unwrap().insert("room".to_string(), r);
+        (rooms, Default::default(), receiver)
+    }
 
+    #[test]
+    fn test_parse_socket_requests() {
+        let chat = serde_json::from_str::<SocketRequest>(r#"{"type": "chat", "message": "hi"}"#).unwrap();
+        assert!(matches!(chat, SocketRequest::Chat(SendMessageRequest { message: Some(_), set_ready: None })));
+        let step = serde_json::from_str::<SocketRequest>(r#"{"type": "move", "path": [[3, 0], [4, 0]], "calculate_path": false}"#).unwrap();
+        assert!(matches!(step, SocketRequest::Move(PublishToARoomRequest { ref path, calculate_path: false }) if path.len() == 2));
+        let color = serde_json::from_str::<SocketRequest>(r#"{"type": "update", "update_type": "ColorChange", "new_color": 4}"#).unwrap();
+        assert!(matches!(color, SocketRequest::Update(UpdateRoomStateRequest { new_color: Some(4), .. })));
+        assert!(serde_json::from_str::<SocketRequest>(r#"{"type": "dance"}"#).is_err());
+        assert!(serde_json::from_str::<SocketRequest>(r#"{"message": "hi"}"#).is_err());
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_dispatch_socket_requests() {
+        let (rooms, timers, mut receiver) = socket_room();
+        let (sender, mut own) = mpsc::unbounded_channel();
+        texts(&mut receiver);
+
+        dispatch(r#"{"type": "chat", "message": "hi"}"#, &sender, "room", &user(1), &rooms, &timers).await;
+        assert!(texts(&mut receiver)[0].contains("chat_message"));
+
+        dispatch(r#"{"type": "chat", "set_ready": true}"#, &sender, "room", &user(1), &rooms, &timers).await;
+        assert!(rooms.read().unwrap()["room"].players[0].ready);
+        texts(&mut receiver);
+
+        dispatch(r#"{"type": "update", "update_type": "Leave"}"#, &sender, "room", &user(1), &rooms, &timers).await;
+        assert!(rooms.read().unwrap()["room"].players.is_empty());
+        assert!(errors(&mut own).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_errors_go_to_the_sender() {
+        let (rooms, timers, mut receiver) = socket_room();
+        let (sender, mut own) = mpsc::unbounded_channel();
+        texts(&mut receiver);
+
+        dispatch("{not json", &sender, "room", &user(1), &rooms, &timers).await;
+        let sent = errors(&mut own);
+        assert_eq!(1, sent.len());
+        assert!(sent[0].contains("Invalid message"));
+
+        // The game has not started, so the move is rejected.
+        dispatch(r#"{"type": "move", "path": [[3, 0]], "calculate_path": false}"#, &sender, "room", &user(1), &rooms, &timers).await;
+        assert!(errors(&mut own)[0].contains("Request failed"));
+        dispatch(r#"{"type": "chat", "message": "hi"}"#, &sender, "nope", &user(1), &rooms, &timers).await;
+        assert!(errors(&mut own)[0].contains("Request failed"));
+        assert!(texts(&mut receiver).is_empty());
+    }
+
+    #[test]
+    fn test_sweep_keeps_reserved_seats() {
+        let mut r = room();
+        r.private = true;
+        r.reserved = vec![(1, PURPLE), (2, YELLOW)].into_iter().collect();
+        let gs = r.game_state.as_mut().unwrap();
+        gs.add_cones(1, PURPLE).unwrap();
+        gs.add_cones(2, YELLOW).unwrap();
+        gs.add_cones(3, GREEN).unwrap();
+        let (_, _first) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+
+        remove_stale_players(&mut r, Duration::from_secs(40));
+        let gs = r.game_state.as_ref().unwrap();
+        assert_eq!(Some(&YELLOW), gs.players_colors.get(&2));
+        assert_eq!(15, gs.get_cones(&2).len());
+        // A color nobody holds is freed.
+        assert!(gs.get_cones(&3).is_empty());
+        assert!(!gs.players_colors.contains_key(&3));
+
+        let (_, mut second) = join_room("room".to_string(), user(2), &mut r, None).unwrap();
+        assert!(texts(&mut second)[0].contains(r#""player_color":4"#));
+        assert_eq!(2, r.players.len());
+        assert!(join_room("room".to_string(), user(3), &mut r, None).is_err());
+    }
+
+    #[test]
+    fn test_sweep_marks_walk_outs() {
+        let mut r = room();
+        r.reserved = vec![(1, PURPLE), (2, YELLOW)].into_iter().collect();
+        let (_, first) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+        let (_, second) = join_room("room".to_string(), user(2), &mut r, None).unwrap();
+        r.game_started = true;
+        drop(first);
+        r.players[0].last_active = Instant::now() - Duration::from_secs(60);
+        remove_stale_players(&mut r, Duration::from_secs(40));
+        assert_eq!(vec![1], r.walked_out);
+
+        // Coming back takes it back, leaving all at once is no walk-out.
+        let (_, first) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+        assert!(r.walked_out.is_empty());
+        drop(first);
+        drop(second);
+        for p in r.players.iter_mut() {
+            p.last_active = Instant::now() - Duration::from_secs(60);
+        }
+        remove_stale_players(&mut r, Duration::from_secs(40));
+        assert!(r.players.is_empty());
+        assert!(r.walked_out.is_empty());
+    }
+
+    #[test]
+    fn test_reconnect_replays_missed_events() {
+        let mut r = room();
+        let (_, _first) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+        send_update(&mut r, &ChatMessage::new("user1", 1, Some("missed".to_string()), None));
+        let (_, mut second) = join_room("room".to_string(), user(1), &mut r, Some(1)).unwrap();
+        let events = texts(&mut second);
+        assert_eq!(2, events.len());
+        assert!(events[0].contains("missed"));
+        assert!(events[1].contains("player_joined"));
+    }
+
+    #[test]
+    fn test_reconnect_after_eviction_asks_for_resync() {
+        let mut r = room();
+        let (_, _first) = join_room("room".to_string(), user(1), &mut r, None).unwrap();
+        for _ in 0..crate::EVENT_LOG_SIZE {
+            send_update(&mut r, &ChatMessage::new("user1", 1, Some("missed".to_string()), None));
+        }
+        let (_, mut second) = join_room("room".to_string(), user(1), &mut r, Some(0)).unwrap();
+        let mut events = Vec::new();
+        while let Ok(Ok(msg)) = second.try_recv() {
+            events.push(msg);
+        }
+        assert!(matches!(&events[0], Message::Text(text) if text.contains("resync_required")));
+        assert!(matches!(&events[1], Message::Sequenced(2, _)));
+        // The resync notice, the events still in the log and the new player_joined.
+        assert_eq!(crate::EVENT_LOG_SIZE + 2, events.len());
+    }
+}